@@ -0,0 +1,394 @@
+use std::mem::MaybeUninit;
+use std::ops::{Index, IndexMut};
+
+use crate::*;
+
+// A handle to an element, returned by insert. It stays valid until that
+// element is removed, no matter what else is inserted or removed. After that
+// it is stale, and never refers to anything again, even once its slot is
+// reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    pub(crate) idx: usize,
+    pub(crate) gen: u64
+}
+
+// What is in a slot of the arena. Vacant slots form a linked list of places
+// to reuse.
+#[derive(Clone, Copy)]
+enum Slot {
+    // gen tells this occupant apart from every other one the slot has had
+    Occupied { gen: u64 },
+    // the next vacant slot, or EMPTY
+    Vacant(usize)
+}
+
+// Where the nodes live. A node stays in the same slot for as long as it is in
+// the tree, so its index can be handed out. Removing a node leaves its slot
+// vacant to be reused by a later insert, instead of moving another node in.
+//
+// Whether a slot is occupied is kept in slots, next to nodes rather than in
+// them. An enum around the node would keep its tag in a niche inside T, and
+// IterMut reads the links of nodes whose values it has already lent out, so
+// it must be able to reach a node without reading any of T's bytes.
+pub(crate) struct Arena<T> {
+    // nodes[i] is initialized exactly when slots[i] is occupied. The two are
+    // always the same length.
+    nodes: Vec<MaybeUninit<Node<T>>>,
+    slots: Vec<Slot>,
+    // first vacant slot, or EMPTY
+    free: usize,
+    // number of occupied slots
+    len: usize,
+    // the generation the next node gets. It only goes up, so even a slot that
+    // is dropped by shrink_to_fit and made again can not bring back an old one.
+    next_gen: u64
+}
+
+impl<T> Arena<T> {
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Arena {
+            nodes: Vec::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            free: EMPTY,
+            len: 0,
+            next_gen: 0
+        }
+    }
+
+    // number of nodes, not slots
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // number of slots, vacant or not. Every index is below this.
+    pub(crate) fn slots_len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    // makes room for additional more nodes, counting vacant slots
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let additional = additional.saturating_sub(self.slots.len() - self.len);
+        self.nodes.reserve(additional);
+        self.slots.reserve(additional);
+    }
+
+    // Drops the vacant slots at the end and frees the spare capacity. Vacant
+    // slots in the middle have to stay, since the nodes after them can not
+    // move without breaking their handles. compact closes those too.
+    // Takes O(slots).
+    pub(crate) fn shrink_to_fit(&mut self) {
+        while let Some(Slot::Vacant(_)) = self.slots.last() {
+            self.slots.pop();
+            self.nodes.pop();
+        }
+        self.slots.shrink_to_fit();
+        self.nodes.shrink_to_fit();
+
+        // some of the vacant slots may be gone, so relink the ones left
+        self.free = EMPTY;
+        for idx in (0..self.slots.len()).rev() {
+            if let Slot::Vacant(ref mut next) = self.slots[idx] {
+                *next = self.free;
+                self.free = idx;
+            }
+        }
+    }
+
+    // Moves every node down into the lowest slots, in index order, so there
+    // are no vacant slots left, and frees the spare capacity. Returns the slot
+    // each old slot moved to, or EMPTY for vacant ones. Nodes keep their
+    // generation, so a handle to a node that moved goes stale instead of
+    // pointing at another one. Takes O(slots).
+    pub(crate) fn compact(&mut self) -> Vec<usize> {
+        let mut moved_to = vec![EMPTY; self.slots.len()];
+        let mut next = 0;
+        for (idx, to) in moved_to.iter_mut().enumerate() {
+            if let Slot::Occupied { .. } = self.slots[idx] {
+                // next is vacant or idx itself, so nothing is overwritten
+                self.nodes.swap(next, idx);
+                self.slots.swap(next, idx);
+                *to = next;
+                next += 1;
+            }
+        }
+        // only vacant slots are past next, and they hold nothing to drop
+        self.nodes.truncate(next);
+        self.slots.truncate(next);
+
+        let remap = |idx: usize| if idx == EMPTY { EMPTY } else { moved_to[idx] };
+        for node in self.nodes.iter_mut() {
+            // every slot left is occupied
+            let node = unsafe { node.assume_init_mut() };
+            node.parent = remap(node.parent);
+            node.left = remap(node.left);
+            node.right = remap(node.right);
+        }
+        self.free = EMPTY;
+        self.nodes.shrink_to_fit();
+        self.slots.shrink_to_fit();
+        moved_to
+    }
+
+    // next_gen is kept, so handles from before stay stale
+    pub(crate) fn clear(&mut self) {
+        self.drop_nodes();
+        self.nodes.clear();
+        self.slots.clear();
+        self.free = EMPTY;
+        self.len = 0;
+    }
+
+    // Drops every node in place. Each slot is marked vacant before its node
+    // is dropped, so if a drop panics the rest are only leaked.
+    fn drop_nodes(&mut self) {
+        for idx in 0..self.slots.len() {
+            if let Slot::Occupied { .. } = self.slots[idx] {
+                self.slots[idx] = Slot::Vacant(EMPTY);
+                unsafe { self.nodes[idx].assume_init_drop() };
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> Option<&Node<T>> {
+        match self.slots.get(idx) {
+            // an occupied slot's node is initialized
+            Some(Slot::Occupied { .. }) => Some(unsafe { self.nodes[idx].assume_init_ref() }),
+            _ => None
+        }
+    }
+
+    // a handle to the node in idx, which must be occupied
+    pub(crate) fn handle(&self, idx: usize) -> NodeId {
+        match self.slots[idx] {
+            Slot::Occupied { gen } => NodeId { idx, gen },
+            Slot::Vacant(_) => panic!("slot {} is vacant", idx)
+        }
+    }
+
+    // the index id points to, or None if its element has been removed
+    pub(crate) fn resolve(&self, id: NodeId) -> Option<usize> {
+        match self.slots.get(id.idx) {
+            Some(&Slot::Occupied { gen }) if gen == id.gen => Some(id.idx),
+            _ => None
+        }
+    }
+
+    // puts n in a vacant slot if there is one, and returns its index
+    pub(crate) fn insert(&mut self, n: Node<T>) -> usize {
+        let slot = Slot::Occupied { gen: self.next_gen };
+        self.next_gen += 1;
+        self.len += 1;
+        if self.free == EMPTY {
+            self.nodes.push(MaybeUninit::new(n));
+            self.slots.push(slot);
+            return self.slots.len() - 1;
+        }
+
+        let idx = self.free;
+        self.free = match std::mem::replace(&mut self.slots[idx], slot) {
+            Slot::Vacant(next) => next,
+            Slot::Occupied { .. } => unreachable!("occupied slot {} on the free list", idx)
+        };
+        // the slot was vacant, so there is nothing here to drop
+        self.nodes[idx] = MaybeUninit::new(n);
+        idx
+    }
+
+    // idx must be occupied, and already unlinked from the tree
+    pub(crate) fn remove(&mut self, idx: usize) -> Node<T> {
+        if let Slot::Vacant(_) = self.slots[idx] {
+            panic!("slot {} is already vacant", idx);
+        }
+        self.slots[idx] = Slot::Vacant(self.free);
+        self.free = idx;
+        self.len -= 1;
+        // the slot is marked vacant now, so the node is read out only once
+        unsafe { self.nodes[idx].assume_init_read() }
+    }
+
+    // the occupied slots, in index order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, &Node<T>)> {
+        self.slots.iter().zip(self.nodes.iter()).enumerate()
+            .filter_map(|(idx, (slot, node))| match slot {
+                Slot::Occupied { .. } => Some((idx, unsafe { node.assume_init_ref() })),
+                Slot::Vacant(_) => None
+            })
+    }
+
+    // Every node, occupied or not, lives at this pointer plus its index.
+    // Occupancy is kept elsewhere, so a node's fields can be read through it
+    // without touching any other bytes of the node.
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut Node<T> {
+        self.nodes.as_mut_ptr().cast()
+    }
+
+    // the same arena with f applied to every value, keeping every index and
+    // generation
+    pub(crate) fn map<U, F: FnMut(T) -> U>(mut self, mut f: F) -> Arena<U> {
+        // taken out, so nothing is dropped twice if f panics. The nodes not
+        // mapped yet are leaked instead.
+        let nodes = std::mem::take(&mut self.nodes);
+        let slots = std::mem::take(&mut self.slots);
+        let nodes = nodes.into_iter().zip(slots.iter()).map(|(node, slot)| match slot {
+            Slot::Occupied { .. } => {
+                let n = unsafe { node.assume_init() };
+                MaybeUninit::new(Node {
+                    val: f(n.val),
+                    color: n.color,
+                    parent: n.parent,
+                    left: n.left,
+                    right: n.right,
+                    size: n.size
+                })
+            },
+            Slot::Vacant(_) => MaybeUninit::uninit()
+        }).collect();
+
+        Arena { nodes, slots, free: self.free, len: self.len, next_gen: self.next_gen }
+    }
+}
+
+impl<T> Drop for Arena<T> {
+    fn drop(&mut self) {
+        self.drop_nodes();
+    }
+}
+
+impl<T> Index<usize> for Arena<T> {
+    type Output = Node<T>;
+
+    fn index(&self, idx: usize) -> &Node<T> {
+        match self.slots[idx] {
+            Slot::Occupied { .. } => unsafe { self.nodes[idx].assume_init_ref() },
+            Slot::Vacant(_) => panic!("slot {} is vacant", idx)
+        }
+    }
+}
+
+impl<T> IndexMut<usize> for Arena<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut Node<T> {
+        match self.slots[idx] {
+            Slot::Occupied { .. } => unsafe { self.nodes[idx].assume_init_mut() },
+            Slot::Vacant(_) => panic!("slot {} is vacant", idx)
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Arena<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_slots_reused() {
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < 10 {
+            b.insert(i);
+            i += 1;
+        }
+
+        b.remove(&3);
+        b.remove(&7);
+        assert!(b.nodes.len() == 8 && b.nodes.slots_len() == 10);
+
+        // the last slot freed is the first reused
+        b.insert(20);
+        b.insert(21);
+        assert!(b.nodes[7].val == 20 && b.nodes[3].val == 21);
+        assert!(b.nodes.slots_len() == 10);
+        assert!(b.validate() == Ok(()));
+    }
+
+    #[test]
+    fn test_shrink_keeps_holes() {
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < 10 {
+            b.insert(i);
+            i += 1;
+        }
+        b.remove(&2);
+        b.remove(&8);
+        b.remove(&9);
+
+        b.shrink_to_fit();
+        assert!(b.nodes.slots_len() == 8);
+        assert!(b.capacity() == 8);
+        b.insert(30);
+        assert!(b.nodes[2].val == 30);
+        b.insert(31);
+        assert!(b.nodes[8].val == 31);
+        assert!(b.validate() == Ok(()));
+    }
+
+    #[test]
+    fn test_handles_survive_removals() {
+        let mut b = BTree::new();
+        let mut ids = Vec::new();
+        let mut i = 0;
+        while i < 100 {
+            ids.push(b.insert(i));
+            i += 1;
+        }
+
+        i = 0;
+        while i < 100 {
+            assert!(b.remove_by_handle(ids[i as usize]) == Some(i));
+            assert!(b.get_by_handle(ids[i as usize]).is_none());
+            i += 3;
+        }
+        i = 0;
+        while i < 100 {
+            let expected = if i % 3 == 0 { None } else { Some(&i) };
+            assert!(b.get_by_handle(ids[i as usize]) == expected);
+            i += 1;
+        }
+        assert!(b.remove_by_handle(ids[0]).is_none());
+        assert!(b.validate() == Ok(()));
+    }
+
+    #[test]
+    fn test_stale_handles() {
+        let mut b = BTree::new();
+        let one = b.insert(1);
+        let two = b.insert(2);
+        let three = b.insert(3);
+        assert!(b.neighbors_of_handle(two) == Some((Some(&1), Some(&3))));
+        assert!(b.neighbors_of_handle(one) == Some((None, Some(&2))));
+
+        // 4 takes over the slot 2 had, but the old handle does not see it
+        b.remove_by_handle(two);
+        let four = b.insert(4);
+        assert!(four.idx == two.idx);
+        assert!(b.get_by_handle(two).is_none());
+        assert!(b.neighbors_of_handle(two).is_none());
+        assert!(b.remove_by_handle(two).is_none());
+        assert!(b.get_by_handle(four) == Some(&4));
+        assert!(b.neighbors_of_handle(three) == Some((Some(&1), Some(&4))));
+
+        // the last slot is dropped and made again
+        b.remove_by_handle(three);
+        b.shrink_to_fit();
+        let five = b.insert(5);
+        assert!(five.idx == three.idx);
+        assert!(b.get_by_handle(three).is_none());
+
+        b.clear();
+        let six = b.insert(6);
+        assert!(six.idx == one.idx);
+        assert!(b.get_by_handle(one).is_none());
+        assert!(b.get_by_handle(six) == Some(&6));
+    }
+}
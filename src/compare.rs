@@ -0,0 +1,197 @@
+use std::cmp::Ordering;
+
+// Decides the order a tree keeps its elements in. The tree stores one of
+// these and runs every comparison through it, so the same type can be kept in
+// different orders without wrapping it.
+pub trait Compare<T: ?Sized> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+// The order T already has. This is what trees use unless they are given
+// something else. It needs Ord rather than PartialOrd: a value like NaN that
+// is not comparable to anything would otherwise be put somewhere no search
+// can find it again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Natural;
+
+impl<T: Ord + ?Sized> Compare<T> for Natural {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+// Orders floats with total_cmp, so plain f32s and f64s can be kept in a tree.
+// -0.0 sorts before 0.0, and NaNs sort after infinity (or before negative
+// infinity, if their sign bit is set) instead of breaking the tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TotalOrder;
+
+impl Compare<f32> for TotalOrder {
+    fn compare(&self, a: &f32, b: &f32) -> Ordering {
+        a.total_cmp(b)
+    }
+}
+
+impl Compare<f64> for TotalOrder {
+    fn compare(&self, a: &f64, b: &f64) -> Ordering {
+        a.total_cmp(b)
+    }
+}
+
+// Float keys that are Ord, using the same order as TotalOrder. These work with
+// the default comparator, and anywhere else an Ord key is needed.
+macro_rules! total_float {
+    ($name:ident, $float:ty) => {
+        #[derive(Debug, Clone, Copy, Default)]
+        pub struct $name(pub $float);
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &$name) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &$name) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $name {
+            fn cmp(&self, other: &$name) -> Ordering {
+                self.0.total_cmp(&other.0)
+            }
+        }
+
+        impl From<$float> for $name {
+            fn from(val: $float) -> $name {
+                $name(val)
+            }
+        }
+    };
+}
+
+total_float!(TotalF32, f32);
+total_float!(TotalF64, f64);
+
+// Any closure or function that compares two elements works too.
+impl<T: ?Sized, F: Fn(&T, &T) -> Ordering> Compare<T> for F {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+    use std::ops::Bound;
+    use crate::*;
+
+    struct CaseInsensitive;
+
+    impl Compare<String> for CaseInsensitive {
+        fn compare(&self, a: &String, b: &String) -> Ordering {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        }
+    }
+
+    #[test]
+    fn test_natural() {
+        assert!(Natural.compare(&1, &2) == Ordering::Less);
+        assert!(Natural.compare("b", "a") == Ordering::Greater);
+        assert!(Natural.compare(&1, &1) == Ordering::Equal);
+    }
+
+    #[test]
+    fn test_nan_keys() {
+        let mut b = BTree::new();
+        for v in [3.0, f64::NAN, -1.0, f64::INFINITY, 0.5].iter() {
+            b.insert(TotalF64(*v));
+        }
+        assert_all(&b);
+
+        // NaN goes last and can be found and removed like anything else
+        assert!(b.last().map(|v| v.0.is_nan()) == Some(true));
+        assert!(b.contains(&TotalF64(f64::NAN)));
+        assert!(b.rank(&TotalF64(f64::INFINITY)) == 3);
+        assert!(b.remove(&TotalF64(f64::NAN)).0.is_nan());
+        assert!(b.try_remove(&TotalF64(f64::NAN)).is_none());
+        assert!(b.size() == 4);
+    }
+
+    #[test]
+    fn test_total_order() {
+        let mut b = BTree::with_comparator(TotalOrder);
+        b.insert(f32::NAN);
+        b.insert(0.0f32);
+        b.insert(-0.0f32);
+        b.insert(-2.5f32);
+        assert_all(&b);
+
+        assert!(b.contains(&f32::NAN));
+        let v: Vec<f32> = b.iter().cloned().collect();
+        assert!(v[0] == -2.5 && v[1].is_sign_negative() && v[2].is_sign_positive());
+        assert!(b.remove(&f32::NAN).is_nan());
+        assert!(!b.contains(&f32::NAN));
+    }
+
+    #[test]
+    fn test_reversed_closure() {
+        let mut b = BTree::with_comparator(|a: &i32, b: &i32| b.cmp(a));
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i);
+            i += 1;
+        }
+        assert_all(&b);
+
+        let v: Vec<i32> = b.iter().cloned().collect();
+        assert!(v == (0..20).rev().collect::<Vec<i32>>());
+        assert!(b.first() == Some(&19));
+        assert!(b.floor(&-1) == Some(&0));
+        assert!(b.ceiling(&25) == Some(&19));
+        assert!(b.range((Bound::Included(15), Bound::Included(5))).count() == 11);
+        assert!(b.rank(&10) == 9);
+        assert!(b.remove(&7) == 7);
+        assert!(!b.contains(&7));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut s = RbSet::with_comparator(CaseInsensitive);
+        assert!(s.insert("Apple".to_string()));
+        assert!(s.insert("banana".to_string()));
+        assert!(!s.insert("APPLE".to_string()));
+        assert!(s.contains(&"aPpLe".to_string()));
+
+        let mut m = RbMap::with_comparator(|a: &String, b: &String| {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        });
+        m.insert("Key".to_string(), 1);
+        assert!(m.insert("KEY".to_string(), 2) == Some(1));
+        assert!(m.get(&"key".to_string()) == Some(&2));
+        assert!(m.size() == 1);
+    }
+
+    #[test]
+    fn test_comparator_setops() {
+        let rev = |a: &i32, b: &i32| b.cmp(a);
+        let mut a = BTree::with_comparator(rev);
+        let mut b = BTree::with_comparator(rev);
+        for v in [1, 3, 5].iter() {
+            a.insert(*v);
+        }
+        for v in [3, 4].iter() {
+            b.insert(*v);
+        }
+
+        let u: Vec<i32> = a.union(&b).cloned().collect();
+        assert!(u == vec![5, 4, 3, 1]);
+
+        let d = a.into_difference(b);
+        assert!(d.iter().cloned().collect::<Vec<i32>>() == vec![5, 1]);
+        assert_all(&d);
+    }
+}
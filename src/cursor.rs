@@ -0,0 +1,331 @@
+use std::borrow::Borrow;
+
+use crate::*;
+
+// A position in the tree that can be stepped back and forth through the parent
+// links. Besides pointing at an element, a cursor can sit on the "ghost" spot
+// past both ends: moving next from the last element or prev from the first
+// lands there, and moving on from the ghost wraps around to the other end.
+pub struct Cursor<'a, T, C = Natural> {
+    tree: &'a BTree<T, C>,
+    // EMPTY for the ghost
+    idx: usize
+}
+
+// A cursor that can also insert and remove elements around where it is.
+// Nodes never move, so the cursor stays put through the rebalancing.
+pub struct CursorMut<'a, T, C = Natural> {
+    tree: &'a mut BTree<T, C>,
+    // EMPTY for the ghost
+    idx: usize
+}
+
+// the element after idx, or the first one if idx is the ghost
+fn step_next<T, C>(b: &BTree<T, C>, idx: usize) -> usize {
+    match (idx, b.root_idx) {
+        (_, EMPTY) => EMPTY,
+        (EMPTY, root) => min_in_subtree(&b.nodes, root),
+        (idx, _) => next_idx(&b.nodes, idx)
+    }
+}
+
+// the element before idx, or the last one if idx is the ghost
+fn step_prev<T, C>(b: &BTree<T, C>, idx: usize) -> usize {
+    match (idx, b.root_idx) {
+        (_, EMPTY) => EMPTY,
+        (EMPTY, root) => max_in_subtree(&b.nodes, root),
+        (idx, _) => prev_idx(&b.nodes, idx)
+    }
+}
+
+impl<'a, T, C> Cursor<'a, T, C> {
+
+    // None at the ghost
+    pub fn current(&self) -> Option<&'a T> {
+        self.tree.val_at(self.idx)
+    }
+
+    // None at the ghost
+    pub fn handle(&self) -> Option<NodeId> {
+        match self.idx {
+            EMPTY => None,
+            idx => Some(self.tree.nodes.handle(idx))
+        }
+    }
+
+    // Takes O(log(n)), but O(1) amortized over a walk through the tree.
+    pub fn move_next(&mut self) {
+        self.idx = step_next(self.tree, self.idx);
+    }
+
+    pub fn move_prev(&mut self) {
+        self.idx = step_prev(self.tree, self.idx);
+    }
+
+    pub fn peek_next(&self) -> Option<&'a T> {
+        self.tree.val_at(step_next(self.tree, self.idx))
+    }
+
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        self.tree.val_at(step_prev(self.tree, self.idx))
+    }
+}
+
+impl<'a, T, C> CursorMut<'a, T, C> {
+
+    // None at the ghost. There is no current_mut, since changing the element
+    // could break the order.
+    pub fn current(&self) -> Option<&T> {
+        self.tree.val_at(self.idx)
+    }
+
+    pub fn handle(&self) -> Option<NodeId> {
+        match self.idx {
+            EMPTY => None,
+            idx => Some(self.tree.nodes.handle(idx))
+        }
+    }
+
+    pub fn move_next(&mut self) {
+        self.idx = step_next(self.tree, self.idx);
+    }
+
+    pub fn move_prev(&mut self) {
+        self.idx = step_prev(self.tree, self.idx);
+    }
+
+    pub fn peek_next(&self) -> Option<&T> {
+        self.tree.val_at(step_next(self.tree, self.idx))
+    }
+
+    pub fn peek_prev(&self) -> Option<&T> {
+        self.tree.val_at(step_prev(self.tree, self.idx))
+    }
+
+    // a read only cursor at the same spot, for as long as this one is borrowed
+    pub fn as_cursor(&self) -> Cursor<'_, T, C> {
+        Cursor { tree: self.tree, idx: self.idx }
+    }
+
+    // Removes the current element and moves on to the next one. Returns None,
+    // and does nothing, at the ghost. Takes O(log(n)).
+    pub fn remove_current(&mut self) -> Option<T> {
+        if self.idx == EMPTY {
+            return None;
+        }
+        // removing only relinks nodes, so next is still the right index after
+        let next = next_idx(&self.tree.nodes, self.idx);
+        let val = self.tree.remove_at(self.idx);
+        self.idx = next;
+        Some(val)
+    }
+}
+
+impl<'a, T, C: Compare<T>> CursorMut<'a, T, C> {
+
+    // Inserts val just before the current element, or at the back if the
+    // cursor is at the ghost. The cursor does not move. Gives val back if it
+    // would not be in order there. Takes O(log(n)).
+    pub fn insert_before(&mut self, val: T) -> Result<NodeId, T> {
+        let prev = step_prev(self.tree, self.idx);
+        if !self.in_order(prev, &val, self.idx) {
+            return Err(val);
+        }
+
+        // the new node goes at the bottom of the gap between prev and idx,
+        // which is the left of idx if that is free, and the right of prev if not
+        let new_idx = if self.idx != EMPTY && self.tree.nodes[self.idx].left == EMPTY {
+            self.tree.insert_at(self.idx, true, val)
+        } else {
+            self.tree.insert_at(prev, false, val)
+        };
+        debug_assert!(assert_all(self.tree));
+        Ok(self.tree.nodes.handle(new_idx))
+    }
+
+    // Inserts val just after the current element, or at the front if the
+    // cursor is at the ghost. The cursor does not move. Gives val back if it
+    // would not be in order there. Takes O(log(n)).
+    pub fn insert_after(&mut self, val: T) -> Result<NodeId, T> {
+        let next = step_next(self.tree, self.idx);
+        if !self.in_order(self.idx, &val, next) {
+            return Err(val);
+        }
+
+        let new_idx = if self.idx != EMPTY && self.tree.nodes[self.idx].right == EMPTY {
+            self.tree.insert_at(self.idx, false, val)
+        } else {
+            self.tree.insert_at(next, true, val)
+        };
+        debug_assert!(assert_all(self.tree));
+        Ok(self.tree.nodes.handle(new_idx))
+    }
+
+    // can val go between before and after, either of which may be EMPTY
+    fn in_order(&self, before: usize, val: &T, after: usize) -> bool {
+        let cmp = &self.tree.cmp;
+        let nodes = &self.tree.nodes;
+        (before == EMPTY || cmp.compare(&nodes[before].val, val) != Ordering::Greater)
+            && (after == EMPTY || cmp.compare(val, &nodes[after].val) != Ordering::Greater)
+    }
+}
+
+impl<T, C> BTree<T, C> {
+
+    pub fn cursor_front(&self) -> Cursor<'_, T, C> {
+        Cursor { tree: self, idx: step_next(self, EMPTY) }
+    }
+
+    pub fn cursor_back(&self) -> Cursor<'_, T, C> {
+        Cursor { tree: self, idx: step_prev(self, EMPTY) }
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T, C> {
+        let idx = step_next(self, EMPTY);
+        CursorMut { tree: self, idx }
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T, C> {
+        let idx = step_prev(self, EMPTY);
+        CursorMut { tree: self, idx }
+    }
+
+    // None if the element id was handed out for has been removed. Takes O(1).
+    pub fn cursor_at_handle(&self, id: NodeId) -> Option<Cursor<'_, T, C>> {
+        let idx = self.nodes.resolve(id)?;
+        Some(Cursor { tree: self, idx })
+    }
+
+    pub fn cursor_at_handle_mut(&mut self, id: NodeId) -> Option<CursorMut<'_, T, C>> {
+        let idx = self.nodes.resolve(id)?;
+        Some(CursorMut { tree: self, idx })
+    }
+}
+
+impl<T, C: Compare<T>> BTree<T, C> {
+
+    // A cursor at an element equal to key, or None if there is none. With
+    // duplicates, no guarantees are made about which one. Takes O(log(n)).
+    pub fn cursor_at<Q: ?Sized>(&self, key: &Q) -> Option<Cursor<'_, T, C>>
+            where T: Borrow<Q>, C: Compare<Q> {
+        match BTree::find(self, |v| self.cmp.compare(key, v.borrow())) {
+            EMPTY => None,
+            idx => Some(Cursor { tree: self, idx })
+        }
+    }
+
+    pub fn cursor_at_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<CursorMut<'_, T, C>>
+            where T: Borrow<Q>, C: Compare<Q> {
+        match BTree::find(self, |v| self.cmp.compare(key, v.borrow())) {
+            EMPTY => None,
+            idx => Some(CursorMut { tree: self, idx })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_cursor_walk() {
+        let b = BTree::build_sorted(0..20, Natural);
+        let mut c = b.cursor_front();
+        let mut i = 0;
+        while let Some(&v) = c.current() {
+            assert!(v == i);
+            c.move_next();
+            i += 1;
+        }
+        assert!(i == 20);
+        // off the end is the ghost, and past that is the front again
+        assert!(c.peek_prev() == Some(&19) && c.peek_next() == Some(&0));
+        c.move_next();
+        assert!(c.current() == Some(&0));
+        c.move_prev();
+        assert!(c.current().is_none());
+        c.move_prev();
+        assert!(c.current() == Some(&19));
+
+        let c = b.cursor_at(&7).unwrap();
+        assert!(c.peek_prev() == Some(&6) && c.peek_next() == Some(&8));
+        assert!(b.cursor_at(&20).is_none());
+        assert!(BTree::<i32>::new().cursor_back().current().is_none());
+    }
+
+    #[test]
+    fn test_cursor_insert() {
+        let mut b = BTree::new();
+        let mut c = b.cursor_front_mut();
+        // at the ghost, before is the back and after is the front
+        assert!(c.insert_before(5).is_ok());
+        assert!(c.insert_after(1).is_ok());
+        assert!(c.insert_before(9).is_ok());
+        assert!(c.insert_before(8) == Err(8));
+
+        c.move_next();
+        c.move_next();
+        assert!(c.current() == Some(&5));
+        let mut i = 2;
+        while i < 5 {
+            c.insert_before(i).unwrap();
+            i += 1;
+        }
+        let mut i = 8;
+        while i > 5 {
+            c.insert_after(i).unwrap();
+            i -= 1;
+        }
+        // duplicates are fine, but the order is checked on both sides
+        assert!(c.insert_after(5).is_ok());
+        assert!(c.insert_before(6) == Err(6));
+        assert!(c.insert_after(7) == Err(7));
+        assert!(c.current() == Some(&5));
+        assert!(c.peek_prev() == Some(&4) && c.peek_next() == Some(&5));
+
+        let vals: Vec<i32> = b.iter().cloned().collect();
+        assert!(vals == vec![1, 2, 3, 4, 5, 5, 6, 7, 8, 9]);
+        assert!(b.validate() == Ok(()));
+    }
+
+    #[test]
+    fn test_cursor_remove() {
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < 50 {
+            b.insert(i);
+            i += 1;
+        }
+
+        // drop every other element in one sweep
+        let mut c = b.cursor_front_mut();
+        while c.current().is_some() {
+            c.remove_current();
+            c.move_next();
+        }
+        assert!(c.remove_current().is_none());
+        assert!(b.size() == 25 && b.validate() == Ok(()));
+
+        let mut c = b.cursor_back_mut();
+        assert!(c.remove_current() == Some(49));
+        assert!(c.current().is_none());
+        assert!(c.as_cursor().peek_prev() == Some(&47));
+    }
+
+    #[test]
+    fn test_cursor_at_handle() {
+        let mut b = BTree::new();
+        b.insert(10);
+        b.insert(30);
+        let id = b.insert(20);
+        let c = b.cursor_at_handle(id).unwrap();
+        assert!(c.peek_prev() == Some(&10) && c.peek_next() == Some(&30));
+        assert!(c.handle() == Some(id));
+
+        let mut c = b.cursor_at_handle_mut(id).unwrap();
+        let new_id = c.insert_after(25).unwrap();
+        c.remove_current();
+        assert!(c.current() == Some(&25) && c.handle() == Some(new_id));
+        assert!(b.cursor_at_handle(id).is_none());
+    }
+}
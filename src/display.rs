@@ -0,0 +1,130 @@
+use crate::*;
+
+// Draws a tree sideways with box drawing characters: the root is on the left,
+// right subtrees above their parent and left subtrees below, so turning the
+// output a quarter turn clockwise gives the usual picture.
+//
+//     ┌── 3 (B)
+//     2 (B)
+//     │   ┌── 1 (R)
+//     └── 0 (B)
+//
+// Each node is marked (R) or (B), or drawn in red or the normal color if ANSI
+// colors are turned on.
+pub struct DisplayTree<'a, T, C> {
+    tree: &'a BTree<T, C>,
+    ansi: bool
+}
+
+// which side of its parent a node hangs off
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Root,
+    Left,
+    Right
+}
+
+impl<'a, T: fmt::Debug, C> DisplayTree<'a, T, C> {
+
+    // color the nodes with ANSI escapes instead of marking them
+    pub fn ansi(mut self, on: bool) -> Self {
+        self.ansi = on;
+        self
+    }
+
+    fn draw(&self, f: &mut fmt::Formatter, idx: usize, prefix: &str, side: Side)
+            -> fmt::Result {
+        let n = &self.tree.nodes[idx];
+
+        // the vertical line carries on past a child only if it heads back
+        // towards the parent's other side. The root's children start flush.
+        if n.right != EMPTY {
+            let bar = match side {
+                Side::Root => "",
+                Side::Left => "│   ",
+                Side::Right => "    "
+            };
+            self.draw(f, n.right, &format!("{}{}", prefix, bar), Side::Right)?;
+        }
+
+        let branch = match side {
+            Side::Root => "",
+            Side::Left => "└── ",
+            Side::Right => "┌── "
+        };
+        match (self.ansi, n.color == RED) {
+            (true, true) => writeln!(f, "{}{}\x1b[31m{:?}\x1b[0m", prefix, branch, n.val)?,
+            (true, false) => writeln!(f, "{}{}{:?}", prefix, branch, n.val)?,
+            (false, true) => writeln!(f, "{}{}{:?} (R)", prefix, branch, n.val)?,
+            (false, false) => writeln!(f, "{}{}{:?} (B)", prefix, branch, n.val)?
+        }
+
+        if n.left != EMPTY {
+            let bar = match side {
+                Side::Root => "",
+                Side::Left => "    ",
+                Side::Right => "│   "
+            };
+            self.draw(f, n.left, &format!("{}{}", prefix, bar), Side::Left)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: fmt::Debug, C> fmt::Display for DisplayTree<'a, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.tree.root_idx == EMPTY {
+            return writeln!(f, "(empty)");
+        }
+        self.draw(f, self.tree.root_idx, "", Side::Root)
+    }
+}
+
+impl<T: fmt::Debug, C> BTree<T, C> {
+
+    // something to print, e.g. println!("{}", tree.display_tree()).
+    // Takes O(n log(n)) to print, for the prefixes.
+    pub fn display_tree(&self) -> DisplayTree<'_, T, C> {
+        DisplayTree { tree: self, ansi: false }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_display_small() {
+        let mut b = BTree::new();
+        assert!(b.display_tree().to_string() == "(empty)\n");
+
+        b.insert(2);
+        b.insert(1);
+        b.insert(3);
+        assert!(b.display_tree().to_string() == concat!(
+            "┌── 3 (R)\n",
+            "2 (B)\n",
+            "└── 1 (R)\n"));
+    }
+
+    #[test]
+    fn test_display_deeper() {
+        let b = BTree::build_sorted(1..8, Natural);
+        assert!(b.display_tree().to_string() == concat!(
+            "    ┌── 7 (B)\n",
+            "┌── 6 (B)\n",
+            "│   └── 5 (B)\n",
+            "4 (B)\n",
+            "│   ┌── 3 (B)\n",
+            "└── 2 (B)\n",
+            "    └── 1 (B)\n"));
+    }
+
+    #[test]
+    fn test_display_ansi() {
+        let mut b = BTree::new();
+        b.insert("x");
+        b.insert("y");
+        assert!(b.display_tree().ansi(true).to_string() == "┌── \x1b[31m\"y\"\x1b[0m\n\"x\"\n");
+    }
+}
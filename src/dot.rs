@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+
+use crate::*;
+
+// What to draw besides the nodes and the edges down to their children.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DotOptions {
+    // a small black box for every empty child slot
+    pub nil_leaves: bool,
+    // a dashed edge from every node back up to its parent
+    pub parent_edges: bool
+}
+
+// the value's Debug output, made safe to put between quotes in a DOT label
+fn escape<T: fmt::Debug>(val: &T) -> String {
+    format!("{:?}", val).replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<T: fmt::Debug, C> BTree<T, C> {
+
+    // Writes the tree as a Graphviz digraph, e.g. for `dot -Tsvg`. Each node
+    // shows its value and arena index and is filled with its color.
+    pub fn to_dot(&self, w: &mut impl Write) -> io::Result<()> {
+        self.to_dot_with(w, DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, w: &mut impl Write, opts: DotOptions) -> io::Result<()> {
+        writeln!(w, "digraph BTree {{")?;
+        writeln!(w, "    node [style=filled, fontcolor=white];")?;
+
+        // every occupied slot in the arena is a node in the tree, so there is
+        // no need to walk it
+        for (idx, n) in self.nodes.iter() {
+            let color = if n.color == RED { "red" } else { "black" };
+            writeln!(w, "    n{} [label=\"{}\\n#{}\", fillcolor={}];",
+                    idx, escape(&n.val), idx, color)?;
+        }
+
+        let mut nils = 0;
+        for (idx, n) in self.nodes.iter() {
+            // left first, so dot keeps the children in order
+            for &child in [n.left, n.right].iter() {
+                if child != EMPTY {
+                    writeln!(w, "    n{} -> n{};", idx, child)?;
+                } else if opts.nil_leaves {
+                    writeln!(w, "    nil{} [label=\"\", shape=box, width=0.2, height=0.2, \
+                            fillcolor=black];", nils)?;
+                    writeln!(w, "    n{} -> nil{};", idx, nils)?;
+                    nils += 1;
+                }
+            }
+            if opts.parent_edges && n.parent != EMPTY {
+                writeln!(w, "    n{} -> n{} [style=dashed, constraint=false];", idx, n.parent)?;
+            }
+        }
+
+        writeln!(w, "}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn dot(b: &BTree<&str>, opts: DotOptions) -> String {
+        let mut out = Vec::new();
+        b.to_dot_with(&mut out, opts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut b = BTree::new();
+        b.insert("b");
+        b.insert("a");
+        b.insert("c\"");
+
+        let out = dot(&b, DotOptions::default());
+        assert!(out.starts_with("digraph BTree {\n"));
+        assert!(out.ends_with("}\n"));
+        assert!(out.contains("    n0 [label=\"\\\"b\\\"\\n#0\", fillcolor=black];\n"));
+        assert!(out.contains("    n1 [label=\"\\\"a\\\"\\n#1\", fillcolor=red];\n"));
+        assert!(out.contains("\\\"c\\\\\\\"\\\"\\n#2"));
+        assert!(out.contains("    n0 -> n1;\n    n0 -> n2;\n"));
+        assert!(!out.contains("nil"));
+        assert!(!out.contains("dashed"));
+    }
+
+    #[test]
+    fn test_to_dot_options() {
+        let mut b = BTree::new();
+        b.insert("b");
+        b.insert("a");
+
+        let opts = DotOptions { nil_leaves: true, parent_edges: true };
+        let out = dot(&b, opts);
+        // a has two nils and b has one
+        assert!(out.matches(" -> nil").count() == 3);
+        assert!(out.find("    n0 -> n1;\n") < out.find("    n0 -> nil0;\n"));
+        assert!(out.contains("    n1 -> n0 [style=dashed, constraint=false];\n"));
+
+        let mut empty = Vec::new();
+        BTree::<i32>::new().to_dot(&mut empty).unwrap();
+        assert!(empty == b"digraph BTree {\n    node [style=filled, fontcolor=white];\n}\n");
+    }
+}
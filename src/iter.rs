@@ -0,0 +1,565 @@
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::*;
+
+// Walks the tree in order by following the parent links, so no stack is
+// needed. front and back are only meaningful while len > 0.
+pub struct Iter<'a, T> {
+    nodes: &'a Arena<T>,
+    front: usize,
+    back: usize,
+    len: usize
+}
+
+impl<'a, T> Iter<'a, T> {
+    pub(crate) fn new<C>(b: &'a BTree<T, C>) -> Self {
+        let (front, back) = if b.root_idx == EMPTY {
+            (EMPTY, EMPTY)
+        } else {
+            (min_in_subtree(&b.nodes, b.root_idx), max_in_subtree(&b.nodes, b.root_idx))
+        };
+
+        Iter { nodes: &b.nodes, front, back, len: b.size() }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.front;
+        self.len -= 1;
+        if self.len > 0 {
+            self.front = next_idx(self.nodes, idx);
+        }
+        Some(&self.nodes[idx].val)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.back;
+        self.len -= 1;
+        if self.len > 0 {
+            self.back = prev_idx(self.nodes, idx);
+        }
+        Some(&self.nodes[idx].val)
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T> Clone for Iter<'a, T> {
+    fn clone(&self) -> Self {
+        Iter { nodes: self.nodes, front: self.front, back: self.back, len: self.len }
+    }
+}
+
+impl<'a, T, C> IntoIterator for &'a BTree<T, C> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+// Like Iter, but hands out mutable references to the values. Only the values
+// may be changed, so it is not exposed for BTree itself where that could
+// break the ordering; the map uses it for values_mut.
+//
+// The values being lent out live in the same nodes as the links we still need
+// to read, so the arena is held as a raw pointer. Links are read through it
+// without ever borrowing a whole node, and each value is lent out at most once.
+pub(crate) struct IterMut<'a, T> {
+    nodes: *mut Node<T>,
+    front: usize,
+    back: usize,
+    len: usize,
+    marker: PhantomData<&'a mut Node<T>>
+}
+
+impl<'a, T> IterMut<'a, T> {
+    pub(crate) fn new<C>(b: &'a mut BTree<T, C>) -> Self {
+        let (front, back) = if b.root_idx == EMPTY {
+            (EMPTY, EMPTY)
+        } else {
+            (min_in_subtree(&b.nodes, b.root_idx), max_in_subtree(&b.nodes, b.root_idx))
+        };
+        let len = b.size();
+
+        IterMut { nodes: b.nodes.as_mut_ptr(), front, back, len, marker: PhantomData }
+    }
+}
+
+impl<'a, T> IterMut<'a, T> {
+    // (parent, left, right) of idx
+    fn links(&self, idx: usize) -> (usize, usize, usize) {
+        // idx is always a node in the arena we were built from, and this only
+        // copies the links out, never touching a value that may be lent out
+        unsafe {
+            let node = self.nodes.add(idx);
+            ((*node).parent, (*node).left, (*node).right)
+        }
+    }
+
+    // same as next_idx, but only reads links
+    fn next_idx(&self, mut idx: usize) -> usize {
+        let (_, _, right) = self.links(idx);
+        if right != EMPTY {
+            idx = right;
+            while self.links(idx).1 != EMPTY {
+                idx = self.links(idx).1;
+            }
+            return idx;
+        }
+
+        let mut parent = self.links(idx).0;
+        while parent != EMPTY && self.links(parent).2 == idx {
+            idx = parent;
+            parent = self.links(idx).0;
+        }
+        parent
+    }
+
+    // same as prev_idx, but only reads links
+    fn prev_idx(&self, mut idx: usize) -> usize {
+        let (_, left, _) = self.links(idx);
+        if left != EMPTY {
+            idx = left;
+            while self.links(idx).2 != EMPTY {
+                idx = self.links(idx).2;
+            }
+            return idx;
+        }
+
+        let mut parent = self.links(idx).0;
+        while parent != EMPTY && self.links(parent).1 == idx {
+            idx = parent;
+            parent = self.links(idx).0;
+        }
+        parent
+    }
+
+    fn val_mut(&mut self, idx: usize) -> &'a mut T {
+        // front and back never hand out the same node twice, so this is the
+        // only reference to the value
+        unsafe { &mut (*self.nodes.add(idx)).val }
+    }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<&'a mut T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.front;
+        self.len -= 1;
+        if self.len > 0 {
+            self.front = self.next_idx(idx);
+        }
+        Some(self.val_mut(idx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<&'a mut T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.back;
+        self.len -= 1;
+        if self.len > 0 {
+            self.back = self.prev_idx(idx);
+        }
+        Some(self.val_mut(idx))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for IterMut<'a, T> {}
+
+impl<'a, T> FusedIterator for IterMut<'a, T> {}
+
+// Like Iter, but stops once front and back meet instead of counting down,
+// since the number of elements in the range is not known up front.
+pub struct Range<'a, T> {
+    nodes: &'a Arena<T>,
+    front: usize,
+    back: usize
+}
+
+impl<'a, T> Range<'a, T> {
+    pub(crate) fn new<Q, C, R>(b: &'a BTree<T, C>, range: R) -> Self
+            where Q: ?Sized, T: Borrow<Q>, C: Compare<Q>, R: RangeBounds<Q> {
+        let front = match range.start_bound() {
+            Bound::Included(key) => BTree::ceiling_idx(b, |v| b.cmp.compare(key, v.borrow()), true),
+            Bound::Excluded(key) => BTree::ceiling_idx(b, |v| b.cmp.compare(key, v.borrow()), false),
+            Bound::Unbounded if b.root_idx != EMPTY => min_in_subtree(&b.nodes, b.root_idx),
+            Bound::Unbounded => EMPTY
+        };
+        let back = match range.end_bound() {
+            Bound::Included(key) => BTree::floor_idx(b, |v| b.cmp.compare(key, v.borrow()), true),
+            Bound::Excluded(key) => BTree::floor_idx(b, |v| b.cmp.compare(key, v.borrow()), false),
+            Bound::Unbounded if b.root_idx != EMPTY => max_in_subtree(&b.nodes, b.root_idx),
+            Bound::Unbounded => EMPTY
+        };
+
+        // the ends can cross if the range is empty or backwards
+        if front == EMPTY || back == EMPTY || b.cmp.compare(b.nodes[back].val.borrow(), b.nodes[front].val.borrow()) == Ordering::Less {
+            Range { nodes: &b.nodes, front: EMPTY, back: EMPTY }
+        } else {
+            Range { nodes: &b.nodes, front, back }
+        }
+    }
+}
+
+impl<'a, T> Iterator for Range<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.front == EMPTY {
+            return None;
+        }
+
+        let idx = self.front;
+        if idx == self.back {
+            self.front = EMPTY;
+            self.back = EMPTY;
+        } else {
+            self.front = next_idx(self.nodes, idx);
+        }
+        Some(&self.nodes[idx].val)
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Range<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.back == EMPTY {
+            return None;
+        }
+
+        let idx = self.back;
+        if idx == self.front {
+            self.front = EMPTY;
+            self.back = EMPTY;
+        } else {
+            self.back = prev_idx(self.nodes, idx);
+        }
+        Some(&self.nodes[idx].val)
+    }
+}
+
+impl<'a, T> FusedIterator for Range<'a, T> {}
+
+impl<'a, T> Clone for Range<'a, T> {
+    fn clone(&self) -> Self {
+        Range { nodes: self.nodes, front: self.front, back: self.back }
+    }
+}
+
+// Owns the arena. Values are taken out of their nodes as they are yielded,
+// and the links are left alone so the in-order walk still works. Whatever is
+// left over gets dropped along with the arena.
+pub struct IntoIter<T> {
+    nodes: Arena<Option<T>>,
+    front: usize,
+    back: usize,
+    len: usize
+}
+
+impl<T> IntoIter<T> {
+    // takes the arena rather than the tree so the comparator can be kept
+    pub(crate) fn new(nodes: Arena<T>, root_idx: usize) -> Self {
+        let (front, back) = if root_idx == EMPTY {
+            (EMPTY, EMPTY)
+        } else {
+            (min_in_subtree(&nodes, root_idx), max_in_subtree(&nodes, root_idx))
+        };
+        let len = nodes.len();
+        let nodes = nodes.map(Some);
+
+        IntoIter { nodes, front, back, len }
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.front;
+        self.len -= 1;
+        if self.len > 0 {
+            self.front = next_idx(&self.nodes, idx);
+        }
+        self.nodes[idx].val.take()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.back;
+        self.len -= 1;
+        if self.len > 0 {
+            self.back = prev_idx(&self.nodes, idx);
+        }
+        self.nodes[idx].val.take()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> FusedIterator for IntoIter<T> {}
+
+impl<T, C> IntoIterator for BTree<T, C> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter::new(self.nodes, self.root_idx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Bound;
+    use crate::*;
+
+    fn make_tree(n: i32) -> BTree<i32> {
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < n {
+            b.insert(i * 7 + (-i % 2) * 13);
+            i += 1;
+        }
+        b
+    }
+
+    fn sorted(n: i32) -> Vec<i32> {
+        let mut v: Vec<i32> = (0..n).map(|i| i * 7 + (-i % 2) * 13).collect();
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn test_iter_empty() {
+        let b = BTree::<i32>::new();
+        let mut it = b.iter();
+        assert!(it.len() == 0);
+        assert!(it.next().is_none());
+        assert!(it.next_back().is_none());
+    }
+
+    #[test]
+    fn test_iter_in_order() {
+        let b = make_tree(20);
+        let v: Vec<i32> = b.iter().cloned().collect();
+        assert!(v == sorted(20));
+
+        let mut count = 0;
+        for _ in &b {
+            count += 1;
+        }
+        assert!(count == 20);
+    }
+
+    #[test]
+    fn test_iter_rev() {
+        let b = make_tree(20);
+        let v: Vec<i32> = b.iter().rev().cloned().collect();
+        let mut expected = sorted(20);
+        expected.reverse();
+        assert!(v == expected);
+    }
+
+    #[test]
+    fn test_iter_both_ends() {
+        let b = make_tree(9);
+        let expected = sorted(9);
+        let mut it = b.iter();
+
+        assert!(it.len() == 9);
+        assert!(it.next() == Some(&expected[0]));
+        assert!(it.next_back() == Some(&expected[8]));
+        assert!(it.len() == 7);
+
+        let mut mid: Vec<i32> = Vec::new();
+        while let Some(v) = it.next() {
+            mid.push(*v);
+            if let Some(v) = it.next_back() {
+                mid.push(*v);
+            }
+        }
+        mid.sort();
+        assert!(mid[..] == expected[1..8]);
+        assert!(it.next().is_none());
+        assert!(it.next_back().is_none());
+    }
+
+    #[test]
+    fn test_iter_dups() {
+        let mut b = BTree::new();
+        for v in [3, 1, 3, 2, 3, 1].iter() {
+            b.insert(*v);
+        }
+        let v: Vec<i32> = b.iter().cloned().collect();
+        assert!(v == vec![1, 1, 2, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let b = make_tree(20);
+        let v: Vec<i32> = b.into_iter().collect();
+        assert!(v == sorted(20));
+
+        let empty = BTree::<i32>::new();
+        assert!(empty.into_iter().next().is_none());
+    }
+
+    #[test]
+    fn test_into_iter_both_ends() {
+        let b = make_tree(9);
+        let expected = sorted(9);
+        let mut it = b.into_iter();
+
+        assert!(it.len() == 9);
+        assert!(it.next_back() == Some(expected[8]));
+        assert!(it.next() == Some(expected[0]));
+        assert!(it.next_back() == Some(expected[7]));
+        assert!(it.len() == 6);
+
+        let rest: Vec<i32> = it.collect();
+        assert!(rest[..] == expected[1..7]);
+    }
+
+    #[test]
+    fn test_into_iter_drops_rest() {
+        use std::rc::Rc;
+
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+        struct Counted(i32, Rc<()>);
+
+        let counter = Rc::new(());
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < 10 {
+            b.insert(Counted(i, Rc::clone(&counter)));
+            i += 1;
+        }
+        assert!(Rc::strong_count(&counter) == 11);
+
+        let mut it = b.into_iter();
+        let first = it.next().unwrap();
+        let last = it.next_back().unwrap();
+        assert!(first.0 == 0 && last.0 == 9);
+        drop(it);
+        assert!(Rc::strong_count(&counter) == 3);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let b = make_tree(20);
+        let all = sorted(20);
+
+        let v: Vec<i32> = b.range(10..30).cloned().collect();
+        let expected: Vec<i32> = all.iter().cloned().filter(|x| *x >= 10 && *x < 30).collect();
+        assert!(v == expected);
+
+        let v: Vec<i32> = b.range(14..=28).cloned().collect();
+        assert!(v == vec![14, 22, 28]);
+
+        let v: Vec<i32> = b.range((Bound::Excluded(14), Bound::Included(28))).cloned().collect();
+        assert!(v == vec![22, 28]);
+
+        let v: Vec<i32> = b.range(..).cloned().collect();
+        assert!(v == all);
+
+        let v: Vec<i32> = b.range(..0).cloned().collect();
+        assert!(v == vec![-6]);
+
+        let v: Vec<i32> = b.range(120..).cloned().collect();
+        assert!(v == vec![120, 126]);
+    }
+
+    #[test]
+    fn test_range_empty() {
+        let b = make_tree(20);
+        assert!(b.range(15..20).next().is_none());
+        assert!(b.range(14..14).next().is_none());
+        assert!(b.range((Bound::Included(30), Bound::Included(10))).next().is_none());
+        assert!(b.range(200..).next().is_none());
+        assert!(b.range(..-6).next().is_none());
+        assert!(BTree::<i32>::new().range(..).next().is_none());
+    }
+
+    #[test]
+    fn test_range_rev() {
+        let b = make_tree(20);
+        let v: Vec<i32> = b.range(0..50).rev().cloned().collect();
+        let mut expected: Vec<i32> = sorted(20).into_iter().filter(|x| *x >= 0 && *x < 50).collect();
+        expected.reverse();
+        assert!(v == expected);
+
+        let mut r = b.range(14..=28);
+        assert!(r.next_back() == Some(&28));
+        assert!(r.next() == Some(&14));
+        assert!(r.next_back() == Some(&22));
+        assert!(r.next().is_none());
+        assert!(r.next_back().is_none());
+    }
+
+    #[test]
+    fn test_range_dups() {
+        let mut b = BTree::new();
+        for v in [3, 1, 3, 2, 3, 1, 4].iter() {
+            b.insert(*v);
+        }
+
+        let v: Vec<i32> = b.range(3..=3).cloned().collect();
+        assert!(v == vec![3, 3, 3]);
+        let v: Vec<i32> = b.range(1..3).cloned().collect();
+        assert!(v == vec![1, 1, 2]);
+        let v: Vec<i32> = b.range((Bound::Excluded(1), Bound::Excluded(4))).cloned().collect();
+        assert!(v == vec![2, 3, 3, 3]);
+
+        assert!(b.range_count(3..=3) == 3);
+        assert!(b.range_count(..) == 7);
+        assert!(b.range_count(5..) == 0);
+    }
+}
@@ -1,15 +1,51 @@
-use std::cmp::PartialOrd;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::vec::Vec;
 use std::fmt;
+use std::ops::{Bound, Index, RangeBounds};
+
+mod arena;
+mod compare;
+mod cursor;
+mod display;
+mod dot;
+mod iter;
+pub mod map;
+pub mod multiset;
+mod set;
+pub mod setops;
+mod sorted;
+mod stats;
+mod validate;
+
+use crate::arena::Arena;
+pub use crate::arena::NodeId;
+pub use crate::compare::{Compare, Natural, TotalF32, TotalF64, TotalOrder};
+pub use crate::cursor::{Cursor, CursorMut};
+pub use crate::display::DisplayTree;
+pub use crate::dot::DotOptions;
+pub use crate::iter::{Iter, IntoIter, Range};
+pub use crate::map::RbMap;
+pub use crate::multiset::RbMultiSet;
+pub use crate::set::RbSet;
+pub use crate::sorted::NotSorted;
+pub use crate::stats::TreeStats;
+pub use crate::validate::InvariantViolation;
 
 
 // Inspired by the doubly linked list implementation 
 // found at http://bluss.github.io/ixlist/target/doc/src/ixlist/lib.rs.html
 // on 2019-03-20.
 
-const DEBUG: bool = true;
+// Prints what the balancing code is doing. Only node indices are printed, so
+// nothing needs T: Debug. Off unless built with the trace feature.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "trace") { println!($($arg)*); }
+    };
+}
 
-const EMPTY: usize = std::usize::MAX;
+const EMPTY: usize = usize::MAX;
 
 const RED: bool = true;
 const BLACK: bool = false;
@@ -19,14 +55,15 @@ struct Node<T> {
     color: bool,
 	parent: usize,
     left: usize,
-    right: usize
+    right: usize,
+    // number of nodes in the subtree rooted here, including this one
+    size: usize
 }
 
-struct RemovalData<T> {
+struct RemovalData {
     parent: usize,
     shifted: usize,
-    color: bool,
-    val: T
+    color: bool
 }
 
 impl<T: fmt::Debug> fmt::Debug for Node<T> {
@@ -36,20 +73,31 @@ impl<T: fmt::Debug> fmt::Debug for Node<T> {
         } else {
             "BLACK"
         };
-        write!(f, "val: {:#?}, color: {}, parent: {}, left: {}, right: {}",
+        write!(f, "val: {:#?}, color: {}, parent: {}, left: {}, right: {}, size: {}",
                 self.val, color, self.parent as isize, self.left as isize,
-                self.right as isize)
+                self.right as isize, self.size)
     }
 }
 
 // A red-black tree represented with an adjacency list
-#[derive(Debug)]
-pub struct BTree<T: PartialOrd> {
-    nodes: Vec<Node<T>>,
-    root_idx: usize
+pub struct BTree<T, C = Natural> {
+    nodes: Arena<T>,
+    root_idx: usize,
+    cmp: C
+}
+
+// the comparator is left out so trees ordered by a closure can still be printed.
+// RbMap, RbSet and RbMultiSet do the same.
+impl<T: fmt::Debug, C> fmt::Debug for BTree<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("BTree")
+            .field("nodes", &self.nodes)
+            .field("root_idx", &self.root_idx)
+            .finish()
+    }
 }
 
-fn assert_colors<T: PartialOrd>(nodes: &Vec<Node<T>>, root_idx: usize) {
+fn assert_colors<T>(nodes: &Arena<T>, root_idx: usize) {
     let left_idx = nodes[root_idx].left;
     let right_idx = nodes[root_idx].right;
 
@@ -62,14 +110,14 @@ fn assert_colors<T: PartialOrd>(nodes: &Vec<Node<T>>, root_idx: usize) {
     }
 
     if left_idx != EMPTY {
-        assert_colors(&nodes, left_idx);
+        assert_colors(nodes, left_idx);
     }
     if right_idx != EMPTY {
-        assert_colors(&nodes, right_idx);
+        assert_colors(nodes, right_idx);
     }
 }
 
-fn assert_black_count<T: PartialOrd>(nodes: &Vec<Node<T>>, root_idx: usize) -> usize {
+fn assert_black_count<T>(nodes: &Arena<T>, root_idx: usize) -> usize {
     let count = if nodes[root_idx].color == BLACK {
         1
     } else {
@@ -78,89 +126,234 @@ fn assert_black_count<T: PartialOrd>(nodes: &Vec<Node<T>>, root_idx: usize) -> u
 
     let left_idx = nodes[root_idx].left;
     let left = if left_idx != EMPTY {
-        assert_black_count(&nodes, left_idx)
+        assert_black_count(nodes, left_idx)
     } else {
         1
     };
 
     let right_idx = nodes[root_idx].right;
     let right = if right_idx != EMPTY {
-        assert_black_count(&nodes, right_idx)
+        assert_black_count(nodes, right_idx)
     } else {
         1
     };
 
     assert!(left == right, "root_idx: {}, black node counts {{right: {}, left: {}}}", 
             root_idx, right, left);
-    return count + left;
+    count + left
 }
 
-fn assert_is_rbtree<T: PartialOrd + fmt::Debug>(b: &BTree<T>) -> bool {
-    if DEBUG { println!("checking: {:#?}", b); }
+fn assert_is_rbtree<T, C>(b: &BTree<T, C>) -> bool {
     assert_colors::<T>(&b.nodes, b.root_idx);
     assert_black_count::<T>(&b.nodes, b.root_idx);
     // this will only execute if the above tests pass
-    return true;
+    true
 }
 
-fn assert_is_bst<T: PartialOrd + fmt::Debug>(nodes: &Vec<Node<T>>, idx: usize) -> bool {
+fn assert_is_bst<T, C: Compare<T>>(nodes: &Arena<T>, idx: usize, cmp: &C) -> bool {
     let left_idx = nodes[idx].left;
 
     if left_idx != EMPTY {
-        assert!(nodes[idx].val >= nodes[left_idx].val,
-            "({}, {:#?}): , has left child ({}, {:#?})",
-            idx, nodes[idx].val, left_idx, nodes[left_idx].val);
-        assert_is_bst(nodes, left_idx);
+        assert!(cmp.compare(&nodes[left_idx].val, &nodes[idx].val) != Ordering::Greater,
+            "{} has a greater left child {}", idx, left_idx);
+        assert_is_bst(nodes, left_idx, cmp);
     }
 
     let right_idx = nodes[idx].right;
     if right_idx != EMPTY {
-        assert!(nodes[idx].val <= nodes[right_idx].val,
-            "({}, {:#?}): , has right child ({}, {:#?})",
-            idx, nodes[idx].val, right_idx, nodes[right_idx].val);
-        assert_is_bst(nodes, right_idx);
+        assert!(cmp.compare(&nodes[idx].val, &nodes[right_idx].val) != Ordering::Greater,
+            "{} has a lesser right child {}", idx, right_idx);
+        assert_is_bst(nodes, right_idx, cmp);
     }
-    return true;
+    true
 }
 
-fn assert_is_dlinked<T: PartialOrd + fmt::Debug>(nodes: &Vec<Node<T>>, idx: usize) -> bool{
+fn assert_is_dlinked<T>(nodes: &Arena<T>, idx: usize) -> bool{
     let left_idx = nodes[idx].left;
 
     if left_idx != EMPTY {
         assert!(nodes[left_idx].parent == idx,
-            "({}, {:#?}) not linked to parent ({}, {:#?})",
-            left_idx, nodes[left_idx].val, idx, nodes[idx].val);
+            "{} not linked to parent {}", left_idx, idx);
         assert_is_dlinked(nodes, left_idx);
     }
 
     let right_idx = nodes[idx].right;
     if right_idx != EMPTY {
         assert!(nodes[right_idx].parent == idx,
-            "({}, {:#?}) not linked to parent ({}, {:#?})",
-            right_idx, nodes[right_idx].val, idx, nodes[idx].val);
+            "{} not linked to parent {}", right_idx, idx);
         assert_is_dlinked(nodes, right_idx);
     }
-    return true;
+    true
 }
 
-fn assert_all<T: PartialOrd + fmt::Debug>(b: &BTree<T>) -> bool {
+fn assert_sizes<T>(nodes: &Arena<T>, idx: usize) -> usize {
+    if idx == EMPTY {
+        return 0;
+    }
+
+    let size = 1 + assert_sizes(nodes, nodes[idx].left) + assert_sizes(nodes, nodes[idx].right);
+    assert!(nodes[idx].size == size, "{} has size {}, but its subtree has {} nodes",
+            idx, nodes[idx].size, size);
+    size
+}
+
+// checks everything that does not depend on how the values are ordered
+fn assert_shape<T, C>(b: &BTree<T, C>) -> bool {
     if b.root_idx != EMPTY {
         assert_is_dlinked(&b.nodes, b.root_idx);
-        assert_is_bst(&b.nodes, b.root_idx);
-        assert_is_rbtree(&b);
+        assert_is_rbtree(b);
+        assert!(assert_sizes(&b.nodes, b.root_idx) == b.size());
+    }
+    // this will only execute if the above tests pass
+    true
+}
+
+fn assert_all<T, C: Compare<T>>(b: &BTree<T, C>) -> bool {
+    if b.root_idx != EMPTY {
+        assert_shape(b);
+        assert_is_bst(&b.nodes, b.root_idx, &b.cmp);
     }
     // this will only execute if the above tests pass
-    return true;
+    true
+}
+
+fn subtree_size<T>(nodes: &Arena<T>, idx: usize) -> usize {
+    if idx == EMPTY {
+        0
+    } else {
+        nodes[idx].size
+    }
+}
+
+fn min_in_subtree<T>(nodes: &Arena<T>, mut idx: usize) -> usize {
+    while nodes[idx].left != EMPTY {
+        idx = nodes[idx].left;
+    }
+    idx
+}
+
+fn max_in_subtree<T>(nodes: &Arena<T>, mut idx: usize) -> usize {
+    while nodes[idx].right != EMPTY {
+        idx = nodes[idx].right;
+    }
+    idx
+}
+
+// the in-order successor of idx, or EMPTY if idx is the last node
+fn next_idx<T>(nodes: &Arena<T>, mut idx: usize) -> usize {
+    if nodes[idx].right != EMPTY {
+        return min_in_subtree(nodes, nodes[idx].right);
+    }
+
+    // climb until we come up out of a left subtree
+    let mut parent = nodes[idx].parent;
+    while parent != EMPTY && nodes[parent].right == idx {
+        idx = parent;
+        parent = nodes[idx].parent;
+    }
+    parent
+}
+
+// the in-order predecessor of idx, or EMPTY if idx is the first node
+fn prev_idx<T>(nodes: &Arena<T>, mut idx: usize) -> usize {
+    if nodes[idx].left != EMPTY {
+        return max_in_subtree(nodes, nodes[idx].left);
+    }
+
+    // climb until we come up out of a right subtree
+    let mut parent = nodes[idx].parent;
+    while parent != EMPTY && nodes[parent].left == idx {
+        idx = parent;
+        parent = nodes[idx].parent;
+    }
+    parent
 }
 
-impl<T: PartialOrd + fmt::Debug> BTree<T> {
+impl<T> BTree<T> {
 
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        BTree { nodes: Vec::<Node<T>>::new(), root_idx: 0 }
+        BTree::with_comparator(Natural)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        BTree::with_capacity_and_comparator(capacity, Natural)
+    }
+}
+
+impl<T, C> BTree<T, C> {
+
+    // an empty tree that orders its elements with cmp instead of Ord
+    pub fn with_comparator(cmp: C) -> Self {
+        BTree { nodes: Arena::with_capacity(0), root_idx: EMPTY, cmp }
+    }
+
+    pub fn with_capacity_and_comparator(capacity: usize, cmp: C) -> Self {
+        BTree { nodes: Arena::with_capacity(capacity), root_idx: EMPTY, cmp }
+    }
+
+    // Builds a perfectly balanced tree straight from values that are already
+    // in order, without comparing anything. Node i of the arena is the i-th
+    // smallest value.
+    fn build_sorted<I: IntoIterator<Item = T>>(vals: I, cmp: C) -> Self {
+        let vals = vals.into_iter();
+        let mut nodes = Arena::with_capacity(vals.size_hint().0);
+        for val in vals {
+            nodes.insert(Self::unlinked(val));
+        }
+        Self::link_arena(nodes, cmp)
+    }
+
+    // a node on its own, for build_sorted to link up later
+    fn unlinked(val: T) -> Node<T> {
+        Node {
+            val,
+            color: BLACK,
+            parent: EMPTY,
+            left: EMPTY,
+            right: EMPTY,
+            size: 1
+        }
+    }
+
+    // links up unlinked nodes that were inserted in order into a fresh arena
+    fn link_arena(mut nodes: Arena<T>, cmp: C) -> Self {
+        let n = nodes.len();
+        if n == 0 {
+            return BTree { nodes, root_idx: EMPTY, cmp };
+        }
+
+        // Every nil is at depth deepest or deepest + 1, so making the deepest
+        // level red keeps the black count the same down every path. That is
+        // only needed if the deepest level is not full.
+        let deepest = (usize::BITS - 1 - n.leading_zeros()) as usize;
+        let full = (n + 1).is_power_of_two();
+        let red_depth = if full || deepest == 0 { EMPTY } else { deepest };
+
+        let root_idx = Self::link_sorted(&mut nodes, 0, n, EMPTY, 0, red_depth);
+        BTree { nodes, root_idx, cmp }
+    }
+
+    // links up nodes [lo, hi) under parent and returns the root of the range
+    fn link_sorted(nodes: &mut Arena<T>, lo: usize, hi: usize, parent: usize,
+            depth: usize, red_depth: usize) -> usize {
+        if lo == hi {
+            return EMPTY;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        nodes[mid].parent = parent;
+        nodes[mid].size = hi - lo;
+        if depth == red_depth {
+            nodes[mid].color = RED;
+        }
+        nodes[mid].left = Self::link_sorted(nodes, lo, mid, mid, depth + 1, red_depth);
+        nodes[mid].right = Self::link_sorted(nodes, mid + 1, hi, mid, depth + 1, red_depth);
+        mid
     }
 
     // parent node must exist
-    fn btree_sib(nodes: &Vec<Node<T>>, idx: usize) -> usize {
+    fn btree_sib(nodes: &Arena<T>, idx: usize) -> usize {
         let par_idx = nodes[idx].parent;
 
         // uncle will be left if parent was right, and vice versa
@@ -171,7 +364,7 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         }
     }
 
-    fn is_black(n: &Vec<Node<T>>, idx: usize) -> bool {
+    fn is_black(n: &Arena<T>, idx: usize) -> bool {
         // empty nodes count as black nodes
         idx == EMPTY || n[idx].color == BLACK
     }
@@ -179,7 +372,7 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
     // this function assumes the node at new_idx is red
     // new_idx must be in [0, nodes.len()), e.g. not EMPTY
     // this function will never return EMPTY
-    fn recolor(nodes: &mut Vec<Node<T>>, mut new_idx: usize) -> usize {
+    fn recolor(nodes: &mut Arena<T>, mut new_idx: usize) -> usize {
         // if both parent and uncle are red, recolor
         // else, cannot recolor
         
@@ -190,9 +383,9 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
                 && nodes[parent_idx].color == RED {
             
             let g_par_idx = nodes[parent_idx].parent;
-            let uncle_idx = BTree::btree_sib(nodes, parent_idx);
+            let uncle_idx = Self::btree_sib(nodes, parent_idx);
             // if uncle is black, cannot recolor
-            if BTree::is_black(nodes, uncle_idx) { break; }
+            if Self::is_black(nodes, uncle_idx) { break; }
 
             nodes[uncle_idx].color = BLACK;
             nodes[g_par_idx].color = RED;
@@ -203,11 +396,11 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         }
 
         // return however far this function was able to go.
-        return new_idx;
+        new_idx
     }
 
     // Links the parent node with the new child. Nothing is done with the old child's link.
-    fn replace_child(nodes: &mut Vec<Node<T>>, old_child: usize, new_child: usize) {
+    fn replace_child(nodes: &mut Arena<T>, old_child: usize, new_child: usize) {
         let p = nodes[old_child].parent;
         if p != EMPTY {
             if nodes[p].left == old_child {
@@ -219,7 +412,7 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
     }
 
     // there must be a left node
-    fn right_rotate(b: &mut BTree<T>, idx: usize) {
+    fn right_rotate(b: &mut BTree<T, C>, idx: usize) {
         let left_idx = b.nodes[idx].left;
 
         let right_of_left_idx = b.nodes[left_idx].right;
@@ -228,12 +421,17 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         }
         b.nodes[idx].left = right_of_left_idx;
 
-        BTree::replace_child(&mut b.nodes, idx, left_idx);
+        Self::replace_child(&mut b.nodes, idx, left_idx);
         b.nodes[left_idx].parent = b.nodes[idx].parent;
 
         b.nodes[idx].parent = left_idx;
         b.nodes[left_idx].right = idx;
 
+        // left_idx now has everything idx used to have
+        b.nodes[left_idx].size = b.nodes[idx].size;
+        b.nodes[idx].size = 1 + subtree_size(&b.nodes, b.nodes[idx].left)
+                + subtree_size(&b.nodes, b.nodes[idx].right);
+
         // set the root if it got shifted
         if idx == b.root_idx {
             b.root_idx = left_idx;
@@ -241,7 +439,7 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
     }
 
     // there must be a right node
-    fn left_rotate(b: &mut BTree<T>, idx: usize) {
+    fn left_rotate(b: &mut BTree<T, C>, idx: usize) {
         let right_idx = b.nodes[idx].right;
 
         let left_of_right_idx = b.nodes[right_idx].left;
@@ -250,19 +448,24 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         }
         b.nodes[idx].right = left_of_right_idx;
 
-        BTree::replace_child(&mut b.nodes, idx, right_idx);
+        Self::replace_child(&mut b.nodes, idx, right_idx);
         b.nodes[right_idx].parent = b.nodes[idx].parent;
 
         b.nodes[idx].parent = right_idx;
         b.nodes[right_idx].left = idx;
 
+        // right_idx now has everything idx used to have
+        b.nodes[right_idx].size = b.nodes[idx].size;
+        b.nodes[idx].size = 1 + subtree_size(&b.nodes, b.nodes[idx].left)
+                + subtree_size(&b.nodes, b.nodes[idx].right);
+
         // set the root if it got shifted
         if idx == b.root_idx {
             b.root_idx = right_idx;
         }
     }
 
-    fn adjust_subtrees(b: &mut BTree<T>, g_par_idx: usize,
+    fn adjust_subtrees(b: &mut BTree<T, C>, g_par_idx: usize,
             parent_idx: usize, child_idx: usize) {
         
         if b.nodes[parent_idx].left == child_idx {
@@ -291,39 +494,42 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         b.nodes[new_g_par_idx].color = BLACK;
     }
 
-    fn balence_insert(b: &mut BTree<T>, mut new_idx: usize) {
-        new_idx = BTree::recolor(&mut b.nodes, new_idx);
+    fn balence_insert(b: &mut BTree<T, C>, mut new_idx: usize) {
+        new_idx = Self::recolor(&mut b.nodes, new_idx);
         // make sure the first node inserted is black
         b.nodes[b.root_idx].color = BLACK;
 
         // if black, no need to adjust the tree
-        if BTree::is_black(&b.nodes, new_idx) { return; }
+        if Self::is_black(&b.nodes, new_idx) { return; }
 
         let parent_idx = b.nodes[new_idx].parent;
         // if parent is black, no red-red path, so don't adjust the tree
-        if BTree::is_black(&b.nodes, parent_idx) { return; }
+        if Self::is_black(&b.nodes, parent_idx) { return; }
 
         // need a grandparent to have an uncle
         let g_par_idx = b.nodes[parent_idx].parent;
         if g_par_idx == EMPTY { return; }
 
-        let uncle_idx = BTree::btree_sib(&b.nodes, parent_idx);
+        let uncle_idx = Self::btree_sib(&b.nodes, parent_idx);
         
-        if BTree::is_black(&b.nodes, uncle_idx) {
+        if Self::is_black(&b.nodes, uncle_idx) {
             BTree::adjust_subtrees(b, g_par_idx, parent_idx, new_idx);
         }
     }
 
-    // finds a node that is able to have val as a child
-    fn find_available_parent(b: &BTree<T>, val: &T) -> usize {
+    // Finds a node that is able to have the probed value as a child, and
+    // whether it goes on the left. cmp compares the probe against a node's value.
+    fn find_available_parent<F: Fn(&T) -> Ordering>(b: &BTree<T, C>, cmp: F) -> (usize, bool) {
         let mut idx = b.root_idx;
         let mut idx_ret;
+        let mut go_left;
 
          loop {
             idx_ret = idx;
             let node = &b.nodes[idx];
 
-            idx = if val.lt(&node.val) {
+            go_left = cmp(&node.val) == Ordering::Less;
+            idx = if go_left {
                 node.left
             } else {
                 node.right
@@ -332,32 +538,154 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
             if idx == EMPTY { break; }
         }
 
-        idx_ret
+        (idx_ret, go_left)
     }
 
-    // finds idx of val
-    fn find(b: &BTree<T>, val: &T) -> usize {
+    // Walks down to the probed value. Returns its idx if it is found, otherwise
+    // EMPTY along with the node it would hang off of and on which side.
+    fn search<F: Fn(&T) -> Ordering>(b: &BTree<T, C>, cmp: F) -> (usize, usize, bool) {
         let mut idx = b.root_idx;
-        let mut idx_ret;
+        let mut parent = EMPTY;
+        let mut go_left = false;
 
-         loop {
-            idx_ret = idx;
+        while idx != EMPTY {
+            let node = &b.nodes[idx];
+
+            go_left = match cmp(&node.val) {
+                Ordering::Equal => return (idx, parent, go_left),
+                Ordering::Less => true,
+                Ordering::Greater => false
+            };
+            parent = idx;
+            idx = if go_left {
+                node.left
+            } else {
+                node.right
+            };
+        }
+
+        (EMPTY, parent, go_left)
+    }
+
+    // finds idx of the probed value, or EMPTY if it is not in the tree
+    fn find<F: Fn(&T) -> Ordering>(b: &BTree<T, C>, cmp: F) -> usize {
+        let mut idx = b.root_idx;
+
+        while idx != EMPTY {
             let node = &b.nodes[idx];
 
-            if val.eq(&node.val) { break; }
+            idx = match cmp(&node.val) {
+                Ordering::Equal => break,
+                Ordering::Less => node.left,
+                Ordering::Greater => node.right
+            };
+        }
 
-            idx = if val.lt(&node.val) {
+        idx
+    }
+
+    // finds the first node at or above the probe (strictly above if !inclusive),
+    // or EMPTY if there is none
+    fn ceiling_idx<F: Fn(&T) -> Ordering>(b: &BTree<T, C>, cmp: F, inclusive: bool) -> usize {
+        let mut idx = b.root_idx;
+        let mut found = EMPTY;
+
+        while idx != EMPTY {
+            let node = &b.nodes[idx];
+            let above = if inclusive {
+                cmp(&node.val) != Ordering::Greater
+            } else {
+                cmp(&node.val) == Ordering::Less
+            };
+
+            idx = if above {
+                found = idx;
                 node.left
             } else {
                 node.right
             };
         }
 
-        idx_ret
+        found
+    }
+
+    // finds the last node at or below the probe (strictly below if !inclusive),
+    // or EMPTY if there is none
+    fn floor_idx<F: Fn(&T) -> Ordering>(b: &BTree<T, C>, cmp: F, inclusive: bool) -> usize {
+        let mut idx = b.root_idx;
+        let mut found = EMPTY;
+
+        while idx != EMPTY {
+            let node = &b.nodes[idx];
+            let below = if inclusive {
+                cmp(&node.val) != Ordering::Less
+            } else {
+                cmp(&node.val) == Ordering::Greater
+            };
+
+            idx = if below {
+                found = idx;
+                node.right
+            } else {
+                node.left
+            };
+        }
+
+        found
+    }
+
+    // counts the nodes below the probe (at or below it if inclusive)
+    fn count_below<F: Fn(&T) -> Ordering>(b: &BTree<T, C>, cmp: F, inclusive: bool) -> usize {
+        let mut idx = b.root_idx;
+        let mut count = 0;
+
+        while idx != EMPTY {
+            let node = &b.nodes[idx];
+            let below = if inclusive {
+                cmp(&node.val) != Ordering::Less
+            } else {
+                cmp(&node.val) == Ordering::Greater
+            };
+
+            idx = if below {
+                count += subtree_size(&b.nodes, node.left) + 1;
+                node.right
+            } else {
+                node.left
+            };
+        }
+
+        count
+    }
+
+    // finds the node with k nodes before it, or EMPTY if k is out of bounds
+    fn select_idx(b: &BTree<T, C>, mut k: usize) -> usize {
+        let mut idx = b.root_idx;
+
+        while idx != EMPTY {
+            let left_size = subtree_size(&b.nodes, b.nodes[idx].left);
+            if k < left_size {
+                idx = b.nodes[idx].left;
+            } else if k == left_size {
+                break;
+            } else {
+                k -= left_size + 1;
+                idx = b.nodes[idx].right;
+            }
+        }
+
+        idx
+    }
+
+    fn val_at(&self, idx: usize) -> Option<&T> {
+        match idx {
+            EMPTY => None,
+            idx => Some(&self.nodes[idx].val)
+        }
     }
 
     // makes sure a node's children link to it
-    fn link_with_children(nodes: &mut Vec<Node<T>>, idx: usize) {
+    fn link_with_children(nodes: &mut Arena<T>, idx: usize) {
         let left_idx = nodes[idx].left;
         if left_idx != EMPTY {
             nodes[left_idx].parent = idx;
@@ -369,12 +697,20 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         }
     }
 
+    // Adds delta to the size of idx and all of its ancestors.
+    fn resize_path(nodes: &mut Arena<T>, mut idx: usize, delta: isize) {
+        while idx != EMPTY {
+            nodes[idx].size = (nodes[idx].size as isize + delta) as usize;
+            idx = nodes[idx].parent;
+        }
+    }
+
     // shifts src up into dest's place. dest's other child must be empty
-    fn shift_up(b: &mut BTree<T>, src: usize, dest: usize) -> usize {
+    fn shift_up(b: &mut BTree<T, C>, src: usize, dest: usize) -> usize {
         if src != EMPTY {
             b.nodes[src].parent = b.nodes[dest].parent;
         }
-        BTree::replace_child(&mut b.nodes, dest, src);
+        Self::replace_child(&mut b.nodes, dest, src);
 
         if b.root_idx == dest {
             b.root_idx = src;
@@ -385,59 +721,39 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
 
     // Moves src over the top of dest, makes dest an orphan.
     // src must be a leaf
-    fn overwrite(b: &mut BTree<T>, src: usize, dest: usize) -> usize {
-        if DEBUG { println!("before mov: {:#?}", b.nodes); }
+    fn overwrite(b: &mut BTree<T, C>, src: usize, dest: usize) -> usize {
+        trace!("moving {} over {}", src, dest);
         if src != EMPTY {
             b.nodes[src].left = b.nodes[dest].left;
             b.nodes[src].right = b.nodes[dest].right;
             b.nodes[src].parent = b.nodes[dest].parent;
-            if DEBUG { println!("during mov: {:#?}", b.nodes); }
-            BTree::link_with_children(&mut b.nodes, src);
+            Self::link_with_children(&mut b.nodes, src);
         }
-        BTree::replace_child(&mut b.nodes, dest, src);
+        Self::replace_child(&mut b.nodes, dest, src);
 
         if b.root_idx == dest {
             b.root_idx = src;
         }
-        if DEBUG { println!("after mov: {:#?}", b.nodes); }
         dest
     }
 
-    fn min_in_subtree(nodes: &Vec<Node<T>>, mut idx: usize) -> usize {
-        while nodes[idx].left != EMPTY {
-            idx = nodes[idx].left;
-        }
-        idx
-    }
-
-    // remove the node from the list, replacing its position with the last
-    // member of the list
-    fn remove_node(nodes: &mut Vec<Node<T>>, to_remove: usize) -> Node<T> {
-        let last = nodes.len() - 1;
-        if DEBUG  { println!("removing idx: {}", to_remove); }
-        return if to_remove == last {
-            BTree::replace_child(nodes, last, EMPTY);
-            nodes.swap_remove(to_remove)
-        } else {
-            BTree::replace_child(nodes, last, to_remove);
-            let hold_this = nodes.swap_remove(to_remove);
-            BTree::link_with_children(nodes, to_remove);
-            hold_this
-        };
-    }
-
-    fn min_shift(b: &mut BTree<T>, idx: usize, min: usize, right_of_min: usize) -> usize {
+    fn min_shift(b: &mut BTree<T, C>, idx: usize, min: usize, right_of_min: usize) -> usize {
         let parent = if b.nodes[min].parent == idx {
             min
         } else {
             b.nodes[min].parent
         };
 
+        // min leaves its spot, which passes through idx on the way up
+        let min_parent = b.nodes[min].parent;
+        Self::resize_path(&mut b.nodes, min_parent, -1);
+        b.nodes[min].size = b.nodes[idx].size;
+
         if parent == min { // min must be a right child for this to happen
             b.nodes[min].left = b.nodes[idx].left;
             b.nodes[min].parent = b.nodes[idx].parent;
-            BTree::replace_child(&mut b.nodes, idx, min);
-            BTree::link_with_children(&mut b.nodes, min);
+            Self::replace_child(&mut b.nodes, idx, min);
+            Self::link_with_children(&mut b.nodes, min);
             if b.root_idx == idx {
                 b.root_idx = min;
             }
@@ -445,90 +761,72 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
             BTree::shift_up(b, right_of_min, min); // min is now orph
             BTree::overwrite(b, min, idx);  // idx is now orph
         }
-        return parent;
+        parent
     }
 
-    fn set_r_data(nodes: &Vec<Node<T>>, idx: usize, shift: usize,
-            r_data: &mut RemovalData<T>) {
+    fn set_r_data(nodes: &Arena<T>, idx: usize, shift: usize,
+            r_data: &mut RemovalData) {
         r_data.shifted = shift;
         r_data.parent = nodes[idx].parent;
-        r_data.color = if BTree::is_black(nodes, shift) {
-            BLACK
+        // shift takes idx's color, so the color that leaves the tree is
+        // shift's, unless there is nothing to shift up
+        r_data.color = if shift == EMPTY {
+            nodes[idx].color
         } else {
-            RED
+            nodes[shift].color
         };
     }
 
-    fn adjust_for_removal(b: &mut BTree<T>, idx: usize, r_data: &mut RemovalData<T>) {
-        let last = b.nodes.len() - 1;
-        if r_data.parent == last {
-            r_data.parent = idx;
-        }
-        if r_data.shifted == last {
-            r_data.shifted = idx;
-        }
-        if b.root_idx == last {
-            b.root_idx = idx;
-        }
-    }
-
-    fn bst_remove(b: &mut BTree<T>, key: T) -> RemovalData<T> {
-        let idx = BTree::find(&b, &key);
-        if DEBUG { println!("removing: {:#?}", key); }
+    // idx must be a node in the tree. Returns the removed value along with
+    // what balence_remove needs to know about the splice.
+    fn bst_remove(b: &mut BTree<T, C>, idx: usize) -> (RemovalData, T) {
+        trace!("removing: {}", idx);
         let mut r_data = RemovalData {
             parent: EMPTY,
             shifted: EMPTY,
-            color: RED,
-            val: key
+            color: RED
         };
 
         if b.nodes[idx].right == EMPTY {
-            if DEBUG { println!("right child is empty: {:#?}", b); }
-            BTree::set_r_data(&b.nodes, idx, b.nodes[idx].left, &mut r_data);
+            trace!("right child is empty");
+            Self::set_r_data(&b.nodes, idx, b.nodes[idx].left, &mut r_data);
 
             if r_data.shifted != EMPTY {
                 b.nodes[r_data.shifted].color = b.nodes[idx].color;
             }
+            Self::resize_path(&mut b.nodes, r_data.parent, -1);
             BTree::shift_up(b, r_data.shifted, idx); // idx is now orph
         } else if b.nodes[idx].left == EMPTY {
-            if DEBUG { println!("left child is empty: {:#?}", b); }
-            BTree::set_r_data(&b.nodes, idx, b.nodes[idx].right, &mut r_data);
+            trace!("left child is empty");
+            Self::set_r_data(&b.nodes, idx, b.nodes[idx].right, &mut r_data);
 
             if r_data.shifted != EMPTY {
                 b.nodes[r_data.shifted].color = b.nodes[idx].color;
             }
+            Self::resize_path(&mut b.nodes, r_data.parent, -1);
             BTree::shift_up(b, r_data.shifted, idx); // idx is now orph
         } else {
-            if DEBUG {
-                println!("has 2 children");
-                println!("tree: {:#?}", b);
-            }
+            trace!("has 2 children");
             // has two children, must find replacement
-            let min = BTree::min_in_subtree(&b.nodes, b.nodes[idx].right);
+            let min = min_in_subtree(&b.nodes, b.nodes[idx].right);
             r_data.shifted = b.nodes[min].right;
             r_data.parent = BTree::min_shift(b, idx, min, r_data.shifted);
             r_data.color = b.nodes[min].color;
             b.nodes[min].color = b.nodes[idx].color;
-            
-            if DEBUG { println!("min: {:#?}", b.nodes[min].val); }
+            trace!("min: {}", min);
         };
 
-        BTree::adjust_for_removal(b, idx, &mut r_data);
-
-        // remove idx from the list and replace it with whatever node is at the end of the list
-        if DEBUG { println!("before remove: {:#?}", b); }
-        r_data.val = BTree::remove_node(&mut b.nodes, idx).val;
-        if DEBUG { println!("bst_remove done: {:#?}", b); }
-        if b.nodes.len() > 0 {
-            debug_assert!(assert_is_bst(&b.nodes, b.root_idx));
+        // idx is unlinked now, so its slot can be given up. Nothing else moves.
+        let val = b.nodes.remove(idx).val;
+        if b.root_idx != EMPTY {
             debug_assert!(assert_is_dlinked(&b.nodes, b.root_idx));
         }
 
-        return r_data;
+        (r_data, val)
     }
 
 
-    fn case_sib_is_red(b: &mut BTree<T>, idx: usize, sib: usize) {
+    fn case_sib_is_red(b: &mut BTree<T, C>, idx: usize, sib: usize) {
         if b.nodes[idx].right == sib {
             BTree::left_rotate(b, idx);
         } else {
@@ -538,15 +836,24 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         b.nodes[sib].color = BLACK;
     }
 
-    fn case_nephew_right_black_left_red(b: &mut BTree<T>, sib: usize) {
-        let left = b.nodes[sib].left;
-        b.nodes[left].color = BLACK;
+    // the near nephew must be red
+    fn case_far_nephew_black(b: &mut BTree<T, C>, idx: usize, sib: usize) {
+        let near = if b.nodes[idx].right == sib {
+            b.nodes[sib].left
+        } else {
+            b.nodes[sib].right
+        };
+        b.nodes[near].color = BLACK;
         b.nodes[sib].color = RED;
-        BTree::right_rotate(b, sib);
+        if b.nodes[idx].right == sib {
+            BTree::right_rotate(b, sib);
+        } else {
+            BTree::left_rotate(b, sib);
+        }
     }
 
-    // note that the left nephew may also be red
-    fn case_nephew_right_red(b: &mut BTree<T>, idx: usize, sib: usize) {
+    // note that the near nephew may also be red
+    fn case_far_nephew_red(b: &mut BTree<T, C>, idx: usize, sib: usize) {
         let neph = if b.nodes[idx].right == sib {
             BTree::left_rotate(b, idx);
             b.nodes[sib].right
@@ -561,7 +868,7 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
         b.nodes[sib].color = holder;
     }
 
-    fn get_sib(nodes: &Vec<Node<T>>, idx: usize, shift: usize) -> usize {
+    fn get_sib(nodes: &Arena<T>, idx: usize, shift: usize) -> usize {
         if nodes[idx].left == shift {
             nodes[idx].right
         } else {
@@ -570,12 +877,11 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
     }
 
     // takes parent of thing removed's black child
-    fn balence_remove(b: &mut BTree<T>, mut idx: usize, mut shift: usize) {
+    fn balence_remove(b: &mut BTree<T, C>, mut idx: usize, mut shift: usize) {
 
         while idx != EMPTY {
-            if DEBUG { println!("balence remove: b: {:#?}, idx: {}, shift: {}",
-                    b, idx, shift); }
-            let sib = BTree::get_sib(&b.nodes, idx, shift);
+            trace!("balence remove: idx: {}, shift: {}", idx, shift as isize);
+            let sib = Self::get_sib(&b.nodes, idx, shift);
             // If sib is empty, then shift must also be empty,
             // thus the tree is already balenced.
             if sib == EMPTY {
@@ -585,12 +891,16 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
             if b.nodes[sib].color == RED {
                 BTree::case_sib_is_red(b, idx, sib);
             } else {
-                let right_nephew = b.nodes[sib].right;
-                let left_nephew = b.nodes[sib].left;
+                // the far nephew is on the same side of sib as sib is of idx
+                let (far_nephew, near_nephew) = if b.nodes[idx].right == sib {
+                    (b.nodes[sib].right, b.nodes[sib].left)
+                } else {
+                    (b.nodes[sib].left, b.nodes[sib].right)
+                };
 
-                if BTree::is_black(&b.nodes, right_nephew)
-                        && BTree::is_black(&b.nodes, left_nephew) {
-                    if DEBUG { println!("both black"); }
+                if Self::is_black(&b.nodes, far_nephew)
+                        && Self::is_black(&b.nodes, near_nephew) {
+                    trace!("both black");
 
                     b.nodes[sib].color = RED;
                     if b.nodes[idx].color == RED {
@@ -599,55 +909,56 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
                     }
                     shift = idx;
                     idx = b.nodes[idx].parent;
-                } else if BTree::is_black(&b.nodes, right_nephew) {
-                    if DEBUG { println!("right neph black"); }
-                    BTree::case_nephew_right_black_left_red(b, sib);
+                } else if Self::is_black(&b.nodes, far_nephew) {
+                    trace!("far neph black");
+                    BTree::case_far_nephew_black(b, idx, sib);
                 } else {
-                    if DEBUG { println!("right neph red"); }
-                    BTree::case_nephew_right_red(b, idx, sib);
+                    trace!("far neph red");
+                    BTree::case_far_nephew_red(b, idx, sib);
                     break;
                 }
             }
         }
     }
 
-    pub fn insert(&mut self, key: T) {
-        // new elements are appended to the end of the list
-        let new_idx = self.nodes.len();
-        let mut n = Node {
-            val: key,
+    // Links val in as the left or right child of parent, which must have a
+    // free slot there, and rebalances. parent is ignored if the tree is empty.
+    fn insert_at(&mut self, parent: usize, go_left: bool, val: T) -> usize {
+        let parent = if self.root_idx == EMPTY { EMPTY } else { parent };
+        // new elements go in the first vacant slot, or the end of the arena
+        let new_idx = self.nodes.insert(Node {
+            val,
             color: RED,
-            parent: EMPTY,
+            parent,
             left: EMPTY,
-            right: EMPTY
-        };
+            right: EMPTY,
+            size: 1
+        });
 
-        if new_idx > 0 {
-            // this will return the parent of where val should go
-            let idx = BTree::find_available_parent(&self, &n.val);
-            let node = &mut self.nodes[idx];
-
-            n.parent = idx;
-
-            if n.val.lt(&node.val) {
-                node.left = new_idx;
+        if parent == EMPTY {
+            self.root_idx = new_idx;
+        } else {
+            if go_left {
+                self.nodes[parent].left = new_idx;
             } else {
-                node.right = new_idx;
+                self.nodes[parent].right = new_idx;
             }
+            Self::resize_path(&mut self.nodes, parent, 1);
         }
 
-        self.nodes.push(n);
         BTree::balence_insert(self, new_idx);
-        debug_assert!(assert_all(&self));
+        debug_assert!(assert_shape(self));
+
+        new_idx
     }
 
-    // key must be in tree
-    pub fn remove(&mut self, key: T) -> T {
-        let res = BTree::bst_remove(self, key);
+    // idx must be a node in the tree
+    fn remove_at(&mut self, idx: usize) -> T {
+        let (res, val) = BTree::bst_remove(self, idx);
 
         // was the node spliced out black
         if res.color == BLACK {
-            if BTree::is_black(&self.nodes, res.shifted) {
+            if Self::is_black(&self.nodes, res.shifted) {
                 // replacement is black, must fix
                 BTree::balence_remove(self, res.parent, res.shifted);
             } else { // replacement is red
@@ -655,14 +966,199 @@ impl<T: PartialOrd + fmt::Debug> BTree<T> {
             }
         }
         
-        debug_assert!(assert_all(self));
+        debug_assert!(assert_shape(self));
 
-        return res.val;
+        val
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        if self.root_idx == EMPTY {
+            return None;
+        }
+        Some(&self.nodes[min_in_subtree(&self.nodes, self.root_idx)].val)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        if self.root_idx == EMPTY {
+            return None;
+        }
+        Some(&self.nodes[max_in_subtree(&self.nodes, self.root_idx)].val)
+    }
+
+    pub fn pop_first(&mut self) -> Option<T> {
+        if self.root_idx == EMPTY {
+            return None;
+        }
+        let idx = min_in_subtree(&self.nodes, self.root_idx);
+        Some(self.remove_at(idx))
+    }
+
+    pub fn pop_last(&mut self) -> Option<T> {
+        if self.root_idx == EMPTY {
+            return None;
+        }
+        let idx = max_in_subtree(&self.nodes, self.root_idx);
+        Some(self.remove_at(idx))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter::new(self)
+    }
+
+    // None if the element id was handed out for has been removed
+    pub fn get_by_handle(&self, id: NodeId) -> Option<&T> {
+        self.nodes.resolve(id).map(|idx| &self.nodes[idx].val)
+    }
+
+    // Takes O(log(n)).
+    pub fn remove_by_handle(&mut self, id: NodeId) -> Option<T> {
+        let idx = self.nodes.resolve(id)?;
+        Some(self.remove_at(idx))
+    }
+
+    // The elements just before and after the one id points to, or None if it
+    // has been removed. Takes O(log(n)).
+    pub fn neighbors_of_handle(&self, id: NodeId) -> Option<(Option<&T>, Option<&T>)> {
+        let idx = self.nodes.resolve(id)?;
+        Some((self.val_at(prev_idx(&self.nodes, idx)), self.val_at(next_idx(&self.nodes, idx))))
+    }
+
+    pub fn select(&self, k: usize) -> Option<&T> {
+        self.val_at(BTree::select_idx(self, k))
     }
 
     pub fn size(&self) -> usize {
         self.nodes.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.root_idx == EMPTY
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.root_idx = EMPTY;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+    }
+
+    // Frees the spare capacity, but only drops the slots left by removals if
+    // they are at the end of the arena, so every handle stays valid. Use
+    // compact to get all of the memory back.
+    pub fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+    }
+
+    // Moves nodes into the slots left by removals and frees the spare
+    // capacity, so the tree takes no more memory than it needs. Handles to
+    // elements that had to move go stale. Takes O(n + removed).
+    pub fn compact(&mut self) {
+        let moved_to = self.nodes.compact();
+        if self.root_idx != EMPTY {
+            self.root_idx = moved_to[self.root_idx];
+        }
+        debug_assert!(assert_shape(self));
+    }
+}
+
+impl<T, C: Compare<T>> BTree<T, C> {
+
+    // the handle stays valid until key is removed
+    pub fn insert(&mut self, key: T) -> NodeId {
+        let (parent, go_left) = if self.root_idx == EMPTY {
+            (EMPTY, false)
+        } else {
+            // this will return the parent of where key should go
+            BTree::find_available_parent(self, |v| self.cmp.compare(&key, v))
+        };
+
+        let idx = self.insert_at(parent, go_left, key);
+        debug_assert!(assert_all(self));
+        self.nodes.handle(idx)
+    }
+
+    // key must be in tree
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> T where T: Borrow<Q>, C: Compare<Q> {
+        self.try_remove(key).expect("key is not in the tree")
+    }
+
+    pub fn try_remove<Q: ?Sized>(&mut self, key: &Q) -> Option<T>
+            where T: Borrow<Q>, C: Compare<Q> {
+        let res = match BTree::find(self, |v| self.cmp.compare(key, v.borrow())) {
+            EMPTY => None,
+            idx => Some(self.remove_at(idx))
+        };
+        debug_assert!(assert_all(self));
+        res
+    }
+
+    pub fn contains<Q: ?Sized>(&self, key: &Q) -> bool where T: Borrow<Q>, C: Compare<Q> {
+        BTree::find(self, |v| self.cmp.compare(key, v.borrow())) != EMPTY
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&T> where T: Borrow<Q>, C: Compare<Q> {
+        self.val_at(BTree::find(self, |v| self.cmp.compare(key, v.borrow())))
+    }
+
+    pub fn floor<Q: ?Sized>(&self, key: &Q) -> Option<&T> where T: Borrow<Q>, C: Compare<Q> {
+        self.val_at(BTree::floor_idx(self, |v| self.cmp.compare(key, v.borrow()), true))
+    }
+
+    pub fn ceiling<Q: ?Sized>(&self, key: &Q) -> Option<&T> where T: Borrow<Q>, C: Compare<Q> {
+        self.val_at(BTree::ceiling_idx(self, |v| self.cmp.compare(key, v.borrow()), true))
+    }
+
+    pub fn predecessor<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+            where T: Borrow<Q>, C: Compare<Q> {
+        self.val_at(BTree::floor_idx(self, |v| self.cmp.compare(key, v.borrow()), false))
+    }
+
+    pub fn successor<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+            where T: Borrow<Q>, C: Compare<Q> {
+        self.val_at(BTree::ceiling_idx(self, |v| self.cmp.compare(key, v.borrow()), false))
+    }
+
+    pub fn range<Q: ?Sized, R: RangeBounds<Q>>(&self, range: R) -> Range<'_, T>
+            where T: Borrow<Q>, C: Compare<Q> {
+        Range::new(self, range)
+    }
+
+    pub fn range_count<Q: ?Sized, R: RangeBounds<Q>>(&self, range: R) -> usize
+            where T: Borrow<Q>, C: Compare<Q> {
+        let below = |key: &Q, inclusive| {
+            BTree::count_below(self, |v| self.cmp.compare(key, v.borrow()), inclusive)
+        };
+        let before = match range.start_bound() {
+            Bound::Included(key) => below(key, false),
+            Bound::Excluded(key) => below(key, true),
+            Bound::Unbounded => 0
+        };
+        let through = match range.end_bound() {
+            Bound::Included(key) => below(key, true),
+            Bound::Excluded(key) => below(key, false),
+            Bound::Unbounded => self.size()
+        };
+
+        through.saturating_sub(before)
+    }
+
+    pub fn rank<Q: ?Sized>(&self, key: &Q) -> usize where T: Borrow<Q>, C: Compare<Q> {
+        BTree::count_below(self, |v| self.cmp.compare(key, v.borrow()), false)
+    }
+}
+
+impl<T, C> Index<usize> for BTree<T, C> {
+    type Output = T;
+
+    fn index(&self, k: usize) -> &T {
+        self.select(k).expect("index out of bounds")
+    }
 }
 
 
@@ -675,7 +1171,7 @@ mod test {
     use std::fs::File;
     use crate::*;
 
-    fn new_tree<T: PartialOrd + fmt::Debug>() -> BTree<T> {
+    fn new_tree<T: Ord>() -> BTree<T> {
         BTree::new()
     }
 
@@ -687,21 +1183,21 @@ mod test {
 
         buf.read_line(&mut line)?;
         // line will be empty at the end of the file
-        while b.size() < 150 && line.len() > 0 {
-            while line.len() > 0 {
+        while b.size() < 150 && !line.is_empty() {
+            while !line.is_empty() {
                 if let Some(ch) = line.pop() {
-                    if DEBUG { println!("inserting: {} to: {:#?}", ch, b); }
+                    trace!("inserting: {} to:\n{}", ch, b.display_tree());
                     b.insert(ch);
                 }
             }
             buf.read_line(&mut line)?;
         }
-        assert_is_rbtree::<char>(&b);
+        assert_is_rbtree(&b);
 
         Ok(b)
     }
 
-    fn size(nodes: &Vec<Node<i32>>, idx: usize) -> usize {
+    fn size(nodes: &Arena<i32>, idx: usize) -> usize {
         if idx == EMPTY {
             return 0;
         }
@@ -718,20 +1214,20 @@ mod test {
             count += size(nodes, right_idx);
         }
 
-        return count;
+        count
     }
 
     #[test]
     fn test_ctor_empty() {
         let a = new_tree::<i32>();
-        if DEBUG { println!("{:#?}", a); }
+        trace!("{}", a.display_tree());
     }
 
     #[test]
     fn test_insert_1() {
         let mut b = new_tree::<i32>();
         b.insert(43);
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -742,7 +1238,7 @@ mod test {
             b.insert(i);
             i += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -751,10 +1247,10 @@ mod test {
         let mut i = 0;
         while i < 3 {
             b.insert(i);
-            if DEBUG { println!("{:#?}", b); }
+            trace!("{}", b.display_tree());
             i += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -765,7 +1261,7 @@ mod test {
             b.insert(i);
             i += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -776,7 +1272,7 @@ mod test {
             b.insert(i);
             i -= 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -787,7 +1283,7 @@ mod test {
             b.insert(i);
             i -= 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -798,7 +1294,7 @@ mod test {
             b.insert(i);
             i += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -810,7 +1306,7 @@ mod test {
             b.insert(arr[idx]);
             idx += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -822,7 +1318,7 @@ mod test {
             b.insert(arr[idx]);
             idx += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -834,7 +1330,7 @@ mod test {
             b.insert(arr[idx]);
             idx += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -846,7 +1342,7 @@ mod test {
             b.insert(arr[idx]);
             idx += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
@@ -856,8 +1352,8 @@ mod test {
         while i < 8 {
             b.insert(i * 7 + (-i % 2) * 13);
             i += 1;
-            if DEBUG { println!("{:#?}", b); }
-            assert_is_rbtree::<i32>(&b);
+            trace!("{}", b.display_tree());
+            assert_is_rbtree(&b);
         }
     }
 
@@ -869,13 +1365,13 @@ mod test {
             b.insert(i * 7 + (-i % 2) * 13);
             i += 1;
         }
-        assert_is_rbtree::<i32>(&b);
+        assert_is_rbtree(&b);
     }
 
     #[test]
     fn test_big() {
         match from_file(".gitignore") {
-            Ok(a) => if DEBUG { println!("{:#?}", a) },
+            Ok(a) => trace!("{}", a.display_tree()),
             Err(e) => println!("{:#?}", e)
         }
     }
@@ -883,7 +1379,7 @@ mod test {
     #[test]
     fn test_even_bigger() {
         match from_file("src/lib.rs") {
-            Ok(a) => if DEBUG { println!("{:#?}", a) },
+            Ok(a) => trace!("{}", a.display_tree()),
             Err(e) => println!("{:#?}", e)
         }
     }
@@ -897,14 +1393,15 @@ mod test {
             i += 1;
         }
 
-        if DEBUG { println!("before bst_remove: {:#?}", b); }
+        trace!("before bst_remove:\n{}", b.display_tree());
 
-        BTree::bst_remove(&mut b, 0);
+        let idx = BTree::find(&b, |v| 0.cmp(v));
+        BTree::bst_remove(&mut b, idx);
 
-        if DEBUG { println!("after bst_remove: {:#?}", b); }
+        trace!("after bst_remove:\n{}", b.display_tree());
         assert_is_dlinked(&b.nodes, b.root_idx);
         assert!(size(&b.nodes, b.root_idx) == 19);
-        assert_is_bst(&b.nodes, b.root_idx);
+        assert_is_bst(&b.nodes, b.root_idx, &b.cmp);
     }
 
     #[test]
@@ -912,8 +1409,9 @@ mod test {
         match from_file(".gitignore") {
             Ok(mut a) => {
                 while a.size() > 0 {
-                    let v = a.remove(a.nodes[a.root_idx].val);
-                    if DEBUG { println!("{:#?}", v); }
+                    let root_val = a.nodes[a.root_idx].val;
+                    let v = a.remove(&root_val);
+                    trace!("{:#?}", v);
                 }
             },
             Err(e) => println!("{:#?}", e)
@@ -932,8 +1430,8 @@ mod test {
 
         while idx > 0 {
             idx -= 1;
-            if DEBUG { println!("idx: {}", idx); }
-            b.remove(arr[idx]);
+            trace!("idx: {}", idx);
+            b.remove(&arr[idx]);
         }
     }
 
@@ -947,8 +1445,9 @@ mod test {
         }
 
         while b.size() > 0 {
-            let v = b.remove(b.nodes[b.root_idx].val);
-            if DEBUG { println!("successfully removed: {:#?}", v); }
+            let root_val = b.nodes[b.root_idx].val;
+            let v = b.remove(&root_val);
+            trace!("successfully removed: {:#?}", v);
         }
     }
 
@@ -962,7 +1461,7 @@ mod test {
             idx += 1;
         }
 
-        assert!(20 == b.remove(20));
+        assert!(20 == b.remove(&20));
     }
 
     #[test]
@@ -975,7 +1474,7 @@ mod test {
             idx += 1;
         }
 
-        assert!(17 == b.remove(17));
+        assert!(17 == b.remove(&17));
     }
 
     #[test]
@@ -988,7 +1487,7 @@ mod test {
             idx += 1;
         }
 
-        assert!(15 == b.remove(15));
+        assert!(15 == b.remove(&15));
     }
 
     #[test]
@@ -1001,6 +1500,451 @@ mod test {
             idx += 1;
         }
 
-        assert!(5 == b.remove(5));
+        assert!(5 == b.remove(&5));
+    }
+
+    #[test]
+    fn test_contains() {
+        let mut b = new_tree::<i32>();
+        let arr = [15, 5, 20, 17];
+        for v in arr.iter() {
+            b.insert(*v);
+        }
+
+        for v in arr.iter() {
+            assert!(b.contains(v));
+        }
+        assert!(!b.contains(&16));
+        assert!(!b.contains(&0));
+        assert!(!b.contains(&100));
+    }
+
+    #[test]
+    fn test_get() {
+        let mut b = new_tree::<i32>();
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i * 7 + (-i % 2) * 13);
+            i += 1;
+        }
+
+        assert!(b.get(&14) == Some(&14));
+        assert!(b.get(&-6) == Some(&-6));
+        assert!(b.get(&15).is_none());
+    }
+
+    #[test]
+    fn test_get_empty() {
+        let mut b = new_tree::<i32>();
+        assert!(b.get(&1).is_none());
+        assert!(!b.contains(&1));
+
+        b.insert(1);
+        b.remove(&1);
+        assert!(b.get(&1).is_none());
+        assert!(!b.contains(&1));
+    }
+
+    #[test]
+    fn test_try_remove() {
+        let mut b = new_tree::<i32>();
+        assert!(b.try_remove(&3).is_none());
+
+        let arr = [15, 5, 20, 17];
+        for v in arr.iter() {
+            b.insert(*v);
+        }
+
+        assert!(b.try_remove(&16).is_none());
+        assert!(b.size() == 4);
+        assert_all(&b);
+
+        assert!(b.try_remove(&17) == Some(17));
+        assert!(b.try_remove(&17).is_none());
+        assert!(b.size() == 3);
+    }
+
+    #[test]
+    fn test_try_remove_dups() {
+        let mut b = new_tree::<i32>();
+        let mut i = 0;
+        while i < 12 {
+            b.insert(i % 3);
+            i += 1;
+        }
+
+        while i > 0 {
+            assert!(b.try_remove(&(i % 3)) == Some(i % 3));
+            i -= 1;
+        }
+        assert!(b.try_remove(&0).is_none());
+        assert!(b.size() == 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_remove_missing() {
+        let mut b = new_tree::<i32>();
+        b.insert(1);
+        b.remove(&2);
+    }
+
+    #[test]
+    fn test_remove_shuffled() {
+        let mut b = new_tree::<i32>();
+        let mut vals = Vec::new();
+        let mut seed: u32 = 7;
+        while vals.len() < 60 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let v = (seed >> 16) as i32 % 25;
+            vals.push(v);
+            b.insert(v);
+        }
+
+        while !vals.is_empty() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let v = vals.swap_remove((seed >> 16) as usize % vals.len());
+            assert!(b.remove(&v) == v);
+        }
+        assert!(b.size() == 0);
+    }
+
+    #[test]
+    fn test_empty_state() {
+        let mut b = new_tree::<i32>();
+        assert!(b.is_empty());
+        assert!(b.root_idx == EMPTY);
+
+        b.insert(4);
+        b.insert(2);
+        assert!(!b.is_empty());
+
+        b.remove(&4);
+        b.remove(&2);
+        assert!(b.is_empty());
+        assert!(b.root_idx == EMPTY);
+
+        // 2 was removed last, so its slot is reused
+        b.insert(3);
+        assert!(b.contains(&3));
+        assert!(b.root_idx == 1);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut b = new_tree::<i32>();
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i);
+            i += 1;
+        }
+
+        b.clear();
+        assert!(b.is_empty());
+        assert!(b.size() == 0);
+        assert!(!b.contains(&3));
+
+        b.insert(7);
+        assert!(b.size() == 1);
+        assert_all(&b);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut b = BTree::<i32>::with_capacity(32);
+        assert!(b.is_empty());
+        assert!(b.capacity() >= 32);
+
+        let mut i = 0;
+        while i < 32 {
+            b.insert(i);
+            i += 1;
+        }
+        assert!(b.capacity() >= 32);
+
+        b.reserve(100);
+        assert!(b.capacity() >= 132);
+
+        while i > 4 {
+            i -= 1;
+            b.remove(&i);
+        }
+        b.shrink_to_fit();
+        assert!(b.capacity() < 132);
+        assert!(b.capacity() >= 4);
+        assert!(b.contains(&3));
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut b = BTree::new();
+        let mut ids = Vec::new();
+        let mut i = 0;
+        while i < 100 {
+            ids.push(b.insert(i));
+            i += 1;
+        }
+        let kept = ids[99];
+
+        // the first elements in are the first out, so their slots are all in
+        // front of the ones still in use
+        i = 0;
+        while i < 90 {
+            b.remove(&i);
+            i += 1;
+        }
+        b.shrink_to_fit();
+        assert!(b.capacity() == 100);
+
+        b.compact();
+        assert!(b.capacity() == 10);
+        assert!(b.validate() == Ok(()));
+        assert!(b.iter().cloned().eq(90..100));
+        assert!(b.get_by_handle(kept).is_none());
+
+        // new elements go on the end again, and never match an old handle
+        let id = b.insert(100);
+        assert!(b.get_by_handle(id) == Some(&100));
+        assert!(b.get_by_handle(ids[10]).is_none());
+        assert!(b.validate() == Ok(()));
+    }
+
+    #[test]
+    fn test_dlinked_walk() {
+        let mut b = new_tree::<i32>();
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i * 7 + (-i % 2) * 13);
+            i += 1;
+        }
+
+        let mut idx = min_in_subtree(&b.nodes, b.root_idx);
+        let mut count = 1;
+        while next_idx(&b.nodes, idx) != EMPTY {
+            let next = next_idx(&b.nodes, idx);
+            assert!(b.nodes[idx].val <= b.nodes[next].val);
+            assert!(prev_idx(&b.nodes, next) == idx);
+            idx = next;
+            count += 1;
+        }
+        assert!(idx == max_in_subtree(&b.nodes, b.root_idx));
+        assert!(count == b.size());
+    }
+
+    #[test]
+    fn test_first_last() {
+        let mut b = new_tree::<i32>();
+        assert!(b.first().is_none());
+        assert!(b.last().is_none());
+
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i * 7 + (-i % 2) * 13);
+            i += 1;
+        }
+        assert!(b.first() == Some(&-6));
+        assert!(b.last() == Some(&126));
+    }
+
+    #[test]
+    fn test_pop_first() {
+        let mut b = new_tree::<i32>();
+        assert!(b.pop_first().is_none());
+
+        let arr = [15, 5, 20, 17, 5, 0, 30];
+        for v in arr.iter() {
+            b.insert(*v);
+        }
+
+        let mut popped = Vec::new();
+        while let Some(v) = b.pop_first() {
+            popped.push(v);
+        }
+        assert!(popped == vec![0, 5, 5, 15, 17, 20, 30]);
+        assert!(b.is_empty());
+    }
+
+    #[test]
+    fn test_pop_last() {
+        let mut b = new_tree::<i32>();
+        assert!(b.pop_last().is_none());
+
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i);
+            i += 1;
+        }
+
+        while i > 0 {
+            i -= 1;
+            assert!(b.pop_last() == Some(i));
+            assert!(b.size() == i as usize);
+        }
+        assert!(b.pop_last().is_none());
+    }
+
+    #[test]
+    fn test_floor_ceiling() {
+        let mut b = new_tree::<i32>();
+        assert!(b.floor(&1).is_none());
+        assert!(b.ceiling(&1).is_none());
+
+        let arr = [10, 20, 30, 40, 50];
+        for v in arr.iter() {
+            b.insert(*v);
+        }
+
+        assert!(b.floor(&25) == Some(&20));
+        assert!(b.floor(&30) == Some(&30));
+        assert!(b.floor(&9).is_none());
+        assert!(b.floor(&99) == Some(&50));
+
+        assert!(b.ceiling(&25) == Some(&30));
+        assert!(b.ceiling(&30) == Some(&30));
+        assert!(b.ceiling(&51).is_none());
+        assert!(b.ceiling(&0) == Some(&10));
+    }
+
+    #[test]
+    fn test_predecessor_successor() {
+        let mut b = new_tree::<i32>();
+        assert!(b.predecessor(&1).is_none());
+        assert!(b.successor(&1).is_none());
+
+        let arr = [10, 20, 20, 30, 40, 50];
+        for v in arr.iter() {
+            b.insert(*v);
+        }
+
+        assert!(b.predecessor(&20) == Some(&10));
+        assert!(b.successor(&20) == Some(&30));
+        assert!(b.predecessor(&25) == Some(&20));
+        assert!(b.successor(&25) == Some(&30));
+        assert!(b.predecessor(&10).is_none());
+        assert!(b.successor(&50).is_none());
+        assert!(b.successor(&0) == Some(&10));
+        assert!(b.predecessor(&60) == Some(&50));
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut b = new_tree::<i32>();
+        assert!(b.rank(&5) == 0);
+
+        let arr = [10, 20, 20, 30, 40, 50];
+        for v in arr.iter() {
+            b.insert(*v);
+        }
+
+        assert!(b.rank(&0) == 0);
+        assert!(b.rank(&10) == 0);
+        assert!(b.rank(&11) == 1);
+        assert!(b.rank(&20) == 1);
+        assert!(b.rank(&30) == 3);
+        assert!(b.rank(&99) == 6);
+    }
+
+    #[test]
+    fn test_select() {
+        let mut b = new_tree::<i32>();
+        assert!(b.select(0).is_none());
+
+        let mut i = 0;
+        while i < 20 {
+            b.insert(i * 7 + (-i % 2) * 13);
+            i += 1;
+        }
+
+        let sorted: Vec<i32> = b.iter().cloned().collect();
+        for (k, v) in sorted.iter().enumerate() {
+            assert!(b.select(k) == Some(v));
+            assert!(b[k] == *v);
+            assert!(b.rank(v) == k);
+        }
+        assert!(b.select(20).is_none());
+    }
+
+    #[test]
+    fn test_select_after_remove() {
+        let mut b = new_tree::<i32>();
+        let mut i = 0;
+        while i < 30 {
+            b.insert(i);
+            i += 1;
+        }
+        while i > 0 {
+            i -= 3;
+            b.remove(&i);
+        }
+
+        let sorted: Vec<i32> = b.iter().cloned().collect();
+        assert!(sorted.len() == 20);
+        for (k, v) in sorted.iter().enumerate() {
+            assert!(b.select(k) == Some(v));
+        }
+        assert!(b.range_count(3..=10) == 5);
+        assert!(b.range_count(..) == 20);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_bounds() {
+        let mut b = new_tree::<i32>();
+        b.insert(1);
+        assert!(b[1] == 1);
+    }
+
+    #[test]
+    fn test_build_sorted() {
+        let mut n = 0;
+        while n < 70 {
+            let b = BTree::build_sorted(0..n, Natural);
+            assert_all(&b);
+            assert!(b.size() == n as usize);
+            assert!(b.iter().cloned().eq(0..n));
+            n += 1;
+        }
+    }
+
+    #[test]
+    fn test_borrowed_lookup() {
+        let mut b = BTree::new();
+        for w in ["pear", "apple", "fig", "kiwi", "date"].iter() {
+            b.insert(w.to_string());
+        }
+
+        assert!(b.contains("fig"));
+        assert!(!b.contains("plum"));
+        assert!(b.get("kiwi").map(|s| s.as_str()) == Some("kiwi"));
+        assert!(b.floor("banana").map(|s| s.as_str()) == Some("apple"));
+        assert!(b.ceiling("banana").map(|s| s.as_str()) == Some("date"));
+        assert!(b.predecessor("fig").map(|s| s.as_str()) == Some("date"));
+        assert!(b.successor("fig").map(|s| s.as_str()) == Some("kiwi"));
+        assert!(b.rank("kiwi") == 3);
+        assert!(b.range::<str, _>((Bound::Included("b"), Bound::Excluded("g"))).count() == 2);
+        assert!(b.range_count::<str, _>((Bound::Included("b"), Bound::Excluded("g"))) == 2);
+        assert!(b.remove("pear") == "pear");
+        assert!(b.try_remove("pear").is_none());
+        assert_all(&b);
+    }
+
+    #[test]
+    fn test_no_debug_needed() {
+        // only ordered, so insert and remove must not need to print it
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct Opaque(i32);
+
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < 30 {
+            b.insert(Opaque((i * 7) % 30));
+            i += 1;
+        }
+        assert_all(&b);
+        while i > 0 {
+            i -= 1;
+            assert!(b.remove(&Opaque(i)).0 == i);
+        }
+        assert!(b.is_empty());
     }
 }
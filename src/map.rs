@@ -0,0 +1,494 @@
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+
+use crate::*;
+use crate::iter::IterMut as RawIterMut;
+
+// A map built on the same arena and balancing code as BTree. Entries are
+// stored as (key, value) pairs and only the key is ever compared.
+pub struct RbMap<K, V, C = Natural> {
+    tree: BTree<(K, V), C>
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, C> fmt::Debug for RbMap<K, V, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RbMap").field("tree", &self.tree).finish()
+    }
+}
+
+pub enum Entry<'a, K, V, C = Natural> {
+    Occupied(OccupiedEntry<'a, K, V, C>),
+    Vacant(VacantEntry<'a, K, V, C>)
+}
+
+pub struct OccupiedEntry<'a, K, V, C = Natural> {
+    tree: &'a mut BTree<(K, V), C>,
+    idx: usize
+}
+
+// Remembers where the search for the key ended, so inserting does not have
+// to walk the tree again.
+pub struct VacantEntry<'a, K, V, C = Natural> {
+    tree: &'a mut BTree<(K, V), C>,
+    key: K,
+    parent: usize,
+    go_left: bool
+}
+
+impl<K: Ord, V> RbMap<K, V> {
+
+    pub fn new() -> Self {
+        RbMap { tree: BTree::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        RbMap { tree: BTree::with_capacity(capacity) }
+    }
+}
+
+// the comparator orders the keys, not the (key, value) pairs
+impl<K, V, C: Compare<K>> RbMap<K, V, C> {
+
+    pub fn with_comparator(cmp: C) -> Self {
+        RbMap { tree: BTree::with_comparator(cmp) }
+    }
+
+    fn find<Q: ?Sized>(&self, key: &Q) -> usize where K: Borrow<Q>, C: Compare<Q> {
+        BTree::find(&self.tree, |e| self.tree.cmp.compare(key, e.0.borrow()))
+    }
+
+    pub fn insert(&mut self, key: K, val: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(mut e) => Some(e.insert(val)),
+            Entry::Vacant(e) => {
+                e.insert(val);
+                None
+            }
+        }
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V> where K: Borrow<Q>, C: Compare<Q> {
+        match self.find(key) {
+            EMPTY => None,
+            idx => Some(&self.tree.nodes[idx].val.1)
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+            where K: Borrow<Q>, C: Compare<Q> {
+        match self.find(key) {
+            EMPTY => None,
+            idx => Some(&mut self.tree.nodes[idx].val.1)
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool where K: Borrow<Q>, C: Compare<Q> {
+        self.find(key) != EMPTY
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q>, C: Compare<Q> {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q: ?Sized>(&mut self, key: &Q) -> Option<(K, V)>
+            where K: Borrow<Q>, C: Compare<Q> {
+        match self.find(key) {
+            EMPTY => None,
+            idx => Some(self.tree.remove_at(idx))
+        }
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, C> {
+        match BTree::search(&self.tree, |e| self.tree.cmp.compare(&key, &e.0)) {
+            (EMPTY, parent, go_left) =>
+                Entry::Vacant(VacantEntry { tree: &mut self.tree, key, parent, go_left }),
+            (idx, _, _) => Entry::Occupied(OccupiedEntry { tree: &mut self.tree, idx })
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.tree.iter() }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: RawIterMut::new(&mut self.tree) }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.tree.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.tree.iter() }
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut { inner: RawIterMut::new(&mut self.tree) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+}
+
+impl<K, V, C: Compare<K> + Default> Default for RbMap<K, V, C> {
+    fn default() -> Self {
+        RbMap::with_comparator(C::default())
+    }
+}
+
+impl<'a, K, V, C> Entry<'a, K, V, C> {
+
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key()
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default)
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default())
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.get_mut());
+                Entry::Occupied(e)
+            },
+            Entry::Vacant(e) => Entry::Vacant(e)
+        }
+    }
+}
+
+impl<'a, K, V: Default, C> Entry<'a, K, V, C> {
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V, C> OccupiedEntry<'a, K, V, C> {
+
+    pub fn key(&self) -> &K {
+        &self.tree.nodes[self.idx].val.0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.tree.nodes[self.idx].val.1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.tree.nodes[self.idx].val.1
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.tree.nodes[self.idx].val.1
+    }
+
+    pub fn insert(&mut self, val: V) -> V {
+        std::mem::replace(self.get_mut(), val)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        self.tree.remove_at(self.idx)
+    }
+}
+
+impl<'a, K, V, C> VacantEntry<'a, K, V, C> {
+
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, val: V) -> &'a mut V {
+        let idx = self.tree.insert_at(self.parent, self.go_left, (self.key, val));
+        &mut self.tree.nodes[idx].val.1
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    inner: crate::Iter<'a, (K, V)>
+}
+
+pub struct IterMut<'a, K, V> {
+    inner: RawIterMut<'a, (K, V)>
+}
+
+pub struct Keys<'a, K, V> {
+    inner: crate::Iter<'a, (K, V)>
+}
+
+pub struct Values<'a, K, V> {
+    inner: crate::Iter<'a, (K, V)>
+}
+
+pub struct ValuesMut<'a, K, V> {
+    inner: RawIterMut<'a, (K, V)>
+}
+
+pub struct IntoIter<K, V> {
+    inner: crate::IntoIter<(K, V)>
+}
+
+// All of the iterators above are thin wrappers that only pick out parts of
+// each entry, so they share one implementation.
+macro_rules! entry_iter {
+    ($name:ident, [$($lt:lifetime)?], $item:ty, |$e:ident| $pick:expr) => {
+        impl<$($lt,)? K, V> Iterator for $name<$($lt,)? K, V> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<$item> {
+                self.inner.next().map(|$e| $pick)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.inner.size_hint()
+            }
+        }
+
+        impl<$($lt,)? K, V> DoubleEndedIterator for $name<$($lt,)? K, V> {
+            fn next_back(&mut self) -> Option<$item> {
+                self.inner.next_back().map(|$e| $pick)
+            }
+        }
+
+        impl<$($lt,)? K, V> ExactSizeIterator for $name<$($lt,)? K, V> {}
+
+        impl<$($lt,)? K, V> FusedIterator for $name<$($lt,)? K, V> {}
+    };
+}
+
+entry_iter!(Iter, ['a], (&'a K, &'a V), |e| (&e.0, &e.1));
+entry_iter!(IterMut, ['a], (&'a K, &'a mut V), |e| (&e.0, &mut e.1));
+entry_iter!(Keys, ['a], &'a K, |e| &e.0);
+entry_iter!(Values, ['a], &'a V, |e| &e.1);
+entry_iter!(ValuesMut, ['a], &'a mut V, |e| &mut e.1);
+entry_iter!(IntoIter, [], (K, V), |e| e);
+
+impl<'a, K, V, C: Compare<K>> IntoIterator for &'a RbMap<K, V, C> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, C: Compare<K>> IntoIterator for &'a mut RbMap<K, V, C> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+impl<K, V, C: Compare<K>> IntoIterator for RbMap<K, V, C> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter { inner: self.tree.into_iter() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use crate::map::Entry;
+
+    fn make_map() -> RbMap<i32, String> {
+        let mut m = RbMap::new();
+        let mut i = 0;
+        while i < 20 {
+            let k = i * 7 + (-i % 2) * 13;
+            m.insert(k, k.to_string());
+            i += 1;
+        }
+        m
+    }
+
+    #[test]
+    fn test_insert_get() {
+        let mut m = make_map();
+        assert!(m.size() == 20);
+        assert!(m.get(&14).map(|s| s.as_str()) == Some("14"));
+        assert!(m.get(&15).is_none());
+        assert!(m.contains_key(&-6));
+
+        assert!(m.insert(14, "fourteen".to_string()) == Some("14".to_string()));
+        assert!(m.size() == 20);
+        assert!(m.get(&14).map(|s| s.as_str()) == Some("fourteen"));
+
+        m.get_mut(&14).unwrap().push('!');
+        assert!(m.get(&14).map(|s| s.as_str()) == Some("fourteen!"));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut m = make_map();
+        assert!(m.remove(&15).is_none());
+        assert!(m.remove(&14) == Some("14".to_string()));
+        assert!(m.remove(&14).is_none());
+        assert!(m.size() == 19);
+
+        let keys: Vec<i32> = m.keys().cloned().collect();
+        for k in keys.iter() {
+            assert!(m.remove(k) == Some(k.to_string()));
+        }
+        assert!(m.is_empty());
+    }
+
+    #[test]
+    fn test_iters() {
+        let mut m = make_map();
+        let keys: Vec<i32> = m.keys().cloned().collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert!(keys == sorted);
+
+        for (k, v) in m.iter() {
+            assert!(k.to_string() == *v);
+        }
+        for v in m.values_mut() {
+            v.push('x');
+        }
+        for (k, v) in &mut m {
+            assert!(format!("{}x", k) == *v);
+            v.pop();
+        }
+
+        let rev: Vec<&String> = m.values().rev().collect();
+        assert!(rev.len() == 20);
+        assert!(rev[0] == "126");
+
+        let owned: Vec<(i32, String)> = m.into_iter().collect();
+        assert!(owned[0] == (-6, "-6".to_string()));
+        assert!(owned.len() == 20);
+    }
+
+    #[test]
+    fn test_values_mut_held_together() {
+        // String and char both keep niches the arena must not read through,
+        // while every value is lent out at once
+        let mut m = make_map();
+        m.remove(&14);
+        let mut vs: Vec<&mut String> = m.values_mut().collect();
+        for v in vs.iter_mut() {
+            v.push('!');
+        }
+        vs[0].push('?');
+        assert!(m.values().all(|v| v.ends_with('!') || v.ends_with("!?")));
+        assert!(m.iter().next().map(|(_, v)| v.as_str()) == Some("-6!?"));
+
+        let mut chars = RbMap::new();
+        let mut i = 0u8;
+        while i < 26 {
+            chars.insert(i, (b'a' + i) as char);
+            i += 1;
+        }
+        let cs: Vec<&mut char> = chars.iter_mut().rev().map(|(_, c)| c).collect();
+        for c in cs {
+            *c = c.to_ascii_uppercase();
+        }
+        assert!(chars.values().cloned().eq((b'A'..=b'Z').map(|b| b as char)));
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut m: RbMap<&str, i32> = RbMap::new();
+        for w in "a b a c b a".split(' ') {
+            *m.entry(w).or_insert(0) += 1;
+        }
+        assert!(m.get(&"a") == Some(&3));
+        assert!(m.get(&"b") == Some(&2));
+        assert!(m.get(&"c") == Some(&1));
+
+        m.entry("a").and_modify(|v| *v *= 10).or_insert(0);
+        m.entry("d").and_modify(|v| *v *= 10).or_insert(4);
+        assert!(m.get(&"a") == Some(&30));
+        assert!(m.get(&"d") == Some(&4));
+
+        assert!(*m.entry("e").or_insert_with(|| 5) == 5);
+        assert!(*m.entry("e").or_default() == 5);
+        assert!(*m.entry("f").or_default() == 0);
+        assert!(m.size() == 6);
+    }
+
+    #[test]
+    fn test_entry_variants() {
+        let mut m = make_map();
+        match m.entry(14) {
+            Entry::Occupied(mut e) => {
+                assert!(*e.key() == 14);
+                assert!(e.get() == "14");
+                assert!(e.insert("x".to_string()) == "14");
+                assert!(e.remove() == "x");
+            },
+            Entry::Vacant(_) => panic!("14 should be occupied")
+        }
+        assert!(!m.contains_key(&14));
+
+        match m.entry(15) {
+            Entry::Occupied(_) => panic!("15 should be vacant"),
+            Entry::Vacant(e) => {
+                assert!(*e.key() == 15);
+                e.insert("15".to_string()).push('!');
+            }
+        }
+        assert!(m.get(&15).map(|s| s.as_str()) == Some("15!"));
+        assert!(m.size() == 20);
+    }
+
+    #[test]
+    fn test_borrowed_keys() {
+        let mut m = RbMap::new();
+        m.insert("one".to_string(), 1);
+        m.insert("two".to_string(), 2);
+        m.insert(String::from("three"), 3);
+
+        assert!(m.get("two") == Some(&2));
+        assert!(m.contains_key("three"));
+        *m.get_mut("one").unwrap() += 10;
+        assert!(m.remove("one") == Some(11));
+        assert!(m.remove_entry("two") == Some(("two".to_string(), 2)));
+        assert!(m.get("one").is_none());
+
+        let mut bytes = RbMap::new();
+        bytes.insert(vec![1u8, 2], "a");
+        assert!(bytes.get(&[1u8, 2][..]) == Some(&"a"));
+    }
+
+    #[test]
+    fn test_debug_with_closure() {
+        let mut m = RbMap::with_comparator(|a: &i32, b: &i32| b.cmp(a));
+        m.insert(1, "one");
+        assert!(format!("{:?}", m).starts_with("RbMap { tree: BTree {"));
+    }
+}
@@ -0,0 +1,321 @@
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+
+use crate::*;
+
+// Keeps one node per distinct element along with how many copies of it there
+// are, instead of one node per copy like BTree does.
+pub struct RbMultiSet<T, C = Natural> {
+    tree: BTree<(T, usize), C>,
+    // total number of copies across all nodes
+    len: usize
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for RbMultiSet<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RbMultiSet")
+            .field("tree", &self.tree)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T: Ord> RbMultiSet<T> {
+
+    pub fn new() -> Self {
+        RbMultiSet { tree: BTree::new(), len: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        RbMultiSet { tree: BTree::with_capacity(capacity), len: 0 }
+    }
+}
+
+// the comparator orders the elements, not the (element, count) pairs
+impl<T, C: Compare<T>> RbMultiSet<T, C> {
+
+    pub fn with_comparator(cmp: C) -> Self {
+        RbMultiSet { tree: BTree::with_comparator(cmp), len: 0 }
+    }
+
+    fn find<Q: ?Sized>(&self, key: &Q) -> usize where T: Borrow<Q>, C: Compare<Q> {
+        BTree::find(&self.tree, |e| self.tree.cmp.compare(key, e.0.borrow()))
+    }
+
+    pub fn insert(&mut self, key: T) {
+        self.insert_n(key, 1);
+    }
+
+    // Adds n copies of key. Adding 0 copies does nothing. Panics, without
+    // changing anything, if there would be more than usize::MAX copies in
+    // all. No single count can overflow before the total does.
+    pub fn insert_n(&mut self, key: T, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let len = self.len.checked_add(n).expect("too many copies to count in a usize");
+
+        match BTree::search(&self.tree, |e| self.tree.cmp.compare(&key, &e.0)) {
+            (EMPTY, parent, go_left) => {
+                self.tree.insert_at(parent, go_left, (key, n));
+            },
+            (idx, _, _) => self.tree.nodes[idx].val.1 += n
+        }
+        self.len = len;
+    }
+
+    pub fn count<Q: ?Sized>(&self, key: &Q) -> usize where T: Borrow<Q>, C: Compare<Q> {
+        match self.find(key) {
+            EMPTY => 0,
+            idx => self.tree.nodes[idx].val.1
+        }
+    }
+
+    pub fn contains<Q: ?Sized>(&self, key: &Q) -> bool where T: Borrow<Q>, C: Compare<Q> {
+        self.find(key) != EMPTY
+    }
+
+    // returns false if there was no copy of key to remove
+    pub fn remove_one<Q: ?Sized>(&mut self, key: &Q) -> bool where T: Borrow<Q>, C: Compare<Q> {
+        let idx = self.find(key);
+        if idx == EMPTY {
+            return false;
+        }
+
+        if self.tree.nodes[idx].val.1 == 1 {
+            self.tree.remove_at(idx);
+        } else {
+            self.tree.nodes[idx].val.1 -= 1;
+        }
+        self.len -= 1;
+        true
+    }
+
+    // returns how many copies were removed
+    pub fn remove_all<Q: ?Sized>(&mut self, key: &Q) -> usize where T: Borrow<Q>, C: Compare<Q> {
+        match self.find(key) {
+            EMPTY => 0,
+            idx => {
+                let (_, n) = self.tree.remove_at(idx);
+                self.len -= n;
+                n
+            }
+        }
+    }
+
+    // yields every copy, so an element with a count of 3 comes up 3 times
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { inner: self.tree.iter(), front: None, back: None, len: self.len }
+    }
+
+    // yields each distinct element once along with its count
+    pub fn counts(&self) -> impl DoubleEndedIterator<Item = (&T, usize)> + ExactSizeIterator {
+        self.tree.iter().map(|e| (&e.0, e.1))
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    pub fn distinct_len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+        self.len = 0;
+    }
+}
+
+impl<T, C: Compare<T> + Default> Default for RbMultiSet<T, C> {
+    fn default() -> Self {
+        RbMultiSet::with_comparator(C::default())
+    }
+}
+
+// Expands each (element, count) node into count copies on demand. The front
+// and back hold whichever node they are part way through.
+pub struct Iter<'a, T> {
+    inner: crate::Iter<'a, (T, usize)>,
+    front: Option<(&'a T, usize)>,
+    back: Option<(&'a T, usize)>,
+    len: usize
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        loop {
+            if let Some((val, ref mut left)) = self.front {
+                if *left > 0 {
+                    *left -= 1;
+                    self.len -= 1;
+                    return Some(val);
+                }
+            }
+
+            // once the middle runs out, finish off whatever the back started
+            self.front = match self.inner.next() {
+                Some(e) => Some((&e.0, e.1)),
+                None => self.back.take()
+            };
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        loop {
+            if let Some((val, ref mut left)) = self.back {
+                if *left > 0 {
+                    *left -= 1;
+                    self.len -= 1;
+                    return Some(val);
+                }
+            }
+
+            self.back = match self.inner.next_back() {
+                Some(e) => Some((&e.0, e.1)),
+                None => self.front.take()
+            };
+        }
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T, C: Compare<T>> IntoIterator for &'a RbMultiSet<T, C> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_counts() {
+        let mut m = RbMultiSet::new();
+        for v in "abracadabra".chars() {
+            m.insert(v);
+        }
+
+        assert!(m.count(&'a') == 5);
+        assert!(m.count(&'b') == 2);
+        assert!(m.count(&'z') == 0);
+        assert!(m.size() == 11);
+        assert!(m.distinct_len() == 5);
+
+        let counts: Vec<(char, usize)> = m.counts().map(|(c, n)| (*c, n)).collect();
+        assert!(counts == vec![('a', 5), ('b', 2), ('c', 1), ('d', 1), ('r', 2)]);
+    }
+
+    #[test]
+    fn test_insert_n() {
+        let mut m = RbMultiSet::new();
+        m.insert_n(7, 1_000_000);
+        m.insert_n(3, 0);
+        m.insert_n(7, 5);
+
+        assert!(m.count(&7) == 1_000_005);
+        assert!(!m.contains(&3));
+        assert!(m.distinct_len() == 1);
+        assert!(m.tree.nodes.len() == 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many copies")]
+    fn test_insert_n_overflow() {
+        let mut m = RbMultiSet::new();
+        m.insert_n(1, usize::MAX - 1);
+        m.insert(2);
+        m.insert(1);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut m = RbMultiSet::new();
+        m.insert_n(1, 3);
+        m.insert_n(2, 2);
+        m.insert(3);
+
+        assert!(m.remove_one(&1));
+        assert!(m.count(&1) == 2);
+        assert!(m.remove_one(&3));
+        assert!(!m.contains(&3));
+        assert!(!m.remove_one(&3));
+        assert!(m.distinct_len() == 2);
+
+        assert!(m.remove_all(&1) == 2);
+        assert!(m.remove_all(&1) == 0);
+        assert!(m.size() == 2);
+
+        m.clear();
+        assert!(m.is_empty());
+        assert!(m.size() == 0);
+    }
+
+    #[test]
+    fn test_iter_expands() {
+        let mut m = RbMultiSet::new();
+        m.insert_n(2, 3);
+        m.insert_n(1, 2);
+        m.insert(5);
+
+        let v: Vec<i32> = m.iter().cloned().collect();
+        assert!(v == vec![1, 1, 2, 2, 2, 5]);
+        let v: Vec<i32> = m.iter().rev().cloned().collect();
+        assert!(v == vec![5, 2, 2, 2, 1, 1]);
+        assert!(m.iter().len() == 6);
+    }
+
+    #[test]
+    fn test_iter_both_ends() {
+        let mut m = RbMultiSet::new();
+        m.insert_n(1, 4);
+
+        let mut it = m.iter();
+        assert!(it.next() == Some(&1));
+        assert!(it.next_back() == Some(&1));
+        assert!(it.next_back() == Some(&1));
+        assert!(it.len() == 1);
+        assert!(it.next() == Some(&1));
+        assert!(it.next().is_none());
+        assert!(it.next_back().is_none());
+
+        m.insert_n(0, 2);
+        m.insert_n(2, 2);
+        let mut it = m.iter();
+        let mut v = Vec::new();
+        while let Some(x) = it.next() {
+            v.push(*x);
+            if let Some(y) = it.next_back() {
+                v.push(*y);
+            }
+        }
+        assert!(v == vec![0, 2, 0, 2, 1, 1, 1, 1]);
+    }
+}
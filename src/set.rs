@@ -0,0 +1,232 @@
+use std::borrow::Borrow;
+use std::ops::RangeBounds;
+
+use crate::*;
+
+// A BTree that never holds two equal elements.
+pub struct RbSet<T, C = Natural> {
+    tree: BTree<T, C>
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for RbSet<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RbSet").field("tree", &self.tree).finish()
+    }
+}
+
+impl<T: Ord> RbSet<T> {
+
+    pub fn new() -> Self {
+        RbSet { tree: BTree::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        RbSet { tree: BTree::with_capacity(capacity) }
+    }
+}
+
+impl<T, C: Compare<T>> RbSet<T, C> {
+
+    pub fn with_comparator(cmp: C) -> Self {
+        RbSet { tree: BTree::with_comparator(cmp) }
+    }
+
+    // returns false, and leaves the set alone, if key is already in the set
+    pub fn insert(&mut self, key: T) -> bool {
+        match BTree::search(&self.tree, |v| self.tree.cmp.compare(&key, v)) {
+            (EMPTY, parent, go_left) => {
+                self.tree.insert_at(parent, go_left, key);
+                true
+            },
+            _ => false
+        }
+    }
+
+    // puts key in the set, handing back the equal element it replaced
+    pub fn replace(&mut self, key: T) -> Option<T> {
+        match BTree::search(&self.tree, |v| self.tree.cmp.compare(&key, v)) {
+            (EMPTY, parent, go_left) => {
+                self.tree.insert_at(parent, go_left, key);
+                None
+            },
+            (idx, _, _) => Some(std::mem::replace(&mut self.tree.nodes[idx].val, key))
+        }
+    }
+
+    pub fn contains<Q: ?Sized>(&self, key: &Q) -> bool where T: Borrow<Q>, C: Compare<Q> {
+        self.tree.contains(key)
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&T> where T: Borrow<Q>, C: Compare<Q> {
+        self.tree.get(key)
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> bool where T: Borrow<Q>, C: Compare<Q> {
+        self.tree.try_remove(key).is_some()
+    }
+
+    pub fn take<Q: ?Sized>(&mut self, key: &Q) -> Option<T> where T: Borrow<Q>, C: Compare<Q> {
+        self.tree.try_remove(key)
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.tree.first()
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.tree.last()
+    }
+
+    pub fn pop_first(&mut self) -> Option<T> {
+        self.tree.pop_first()
+    }
+
+    pub fn pop_last(&mut self) -> Option<T> {
+        self.tree.pop_last()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.tree.iter()
+    }
+
+    pub fn range<Q: ?Sized, R: RangeBounds<Q>>(&self, range: R) -> Range<'_, T>
+            where T: Borrow<Q>, C: Compare<Q> {
+        self.tree.range(range)
+    }
+
+    pub fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+}
+
+impl<T, C: Compare<T> + Default> Default for RbSet<T, C> {
+    fn default() -> Self {
+        RbSet::with_comparator(C::default())
+    }
+}
+
+impl<'a, T, C: Compare<T>> IntoIterator for &'a RbSet<T, C> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T, C: Compare<T>> IntoIterator for RbSet<T, C> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        self.tree.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_insert_unique() {
+        let mut s = RbSet::new();
+        assert!(s.insert(5));
+        assert!(s.insert(3));
+        assert!(!s.insert(5));
+        assert!(s.insert(8));
+        assert!(!s.insert(3));
+
+        assert!(s.size() == 3);
+        let v: Vec<i32> = s.iter().cloned().collect();
+        assert!(v == vec![3, 5, 8]);
+    }
+
+    #[test]
+    fn test_duplicate_leaves_arena() {
+        let mut s = RbSet::new();
+        let mut i = 0;
+        while i < 20 {
+            s.insert(i);
+            i += 1;
+        }
+
+        let capacity = s.tree.capacity();
+        while i > 0 {
+            i -= 1;
+            assert!(!s.insert(i));
+        }
+        assert!(s.tree.nodes.len() == 20);
+        assert!(s.tree.capacity() == capacity);
+    }
+
+    #[test]
+    fn test_replace() {
+        #[derive(Debug)]
+        struct Tagged(i32, char);
+
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Tagged) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl Eq for Tagged {}
+
+        impl PartialOrd for Tagged {
+            fn partial_cmp(&self, other: &Tagged) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Tagged {
+            fn cmp(&self, other: &Tagged) -> std::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let mut s = RbSet::new();
+        assert!(s.replace(Tagged(1, 'a')).is_none());
+        assert!(s.replace(Tagged(2, 'b')).is_none());
+        let old = s.replace(Tagged(1, 'c'));
+        assert!(old.map(|t| t.1) == Some('a'));
+        assert!(s.get(&Tagged(1, ' ')).map(|t| t.1) == Some('c'));
+        assert!(s.size() == 2);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut s = RbSet::new();
+        for v in [4, 2, 6, 1, 3].iter() {
+            s.insert(*v);
+        }
+
+        assert!(s.remove(&2));
+        assert!(!s.remove(&2));
+        assert!(s.take(&6) == Some(6));
+        assert!(!s.contains(&6));
+        assert!(s.pop_first() == Some(1));
+        assert!(s.pop_last() == Some(4));
+        assert!(s.into_iter().collect::<Vec<i32>>() == vec![3]);
+    }
+
+    #[test]
+    fn test_borrowed_lookup() {
+        let mut s = RbSet::new();
+        s.insert("b".to_string());
+        s.insert("d".to_string());
+
+        assert!(s.contains("b"));
+        assert!(s.get("d").map(|v| v.as_str()) == Some("d"));
+        assert!(s.range::<str, _>((Bound::Included("a"), Bound::Excluded("c"))).count() == 1);
+        assert!(s.take("b") == Some("b".to_string()));
+        assert!(!s.remove("b"));
+    }
+}
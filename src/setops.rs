@@ -0,0 +1,293 @@
+use std::iter::{FusedIterator, Peekable};
+
+use crate::*;
+
+// Set algebra between two trees, done by walking both in order side by side.
+// Duplicates are matched up one for one: if a value shows up m times on the
+// left and n times on the right, the union has max(m, n) copies of it, the
+// intersection min(m, n), the difference m - n and the symmetric difference
+// |m - n|.
+
+// what came off the left and right sides in one step of a Merge
+type Step<T> = (Option<T>, Option<T>);
+
+// Walks two sorted iterators together. Each step hands back the smaller
+// head, or both heads if they are equal. The order is passed in to each call
+// so the borrowed and owned walks can share this.
+struct Merge<I: Iterator> {
+    a: Peekable<I>,
+    b: Peekable<I>
+}
+
+impl<I: Iterator> Merge<I> {
+    fn new(a: I, b: I) -> Self {
+        Merge { a: a.peekable(), b: b.peekable() }
+    }
+
+    fn step<F: Fn(&I::Item, &I::Item) -> Ordering>(&mut self, cmp: &F) -> Option<Step<I::Item>> {
+        let ord = match (self.a.peek(), self.b.peek()) {
+            (None, None) => return None,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(x), Some(y)) => cmp(x, y)
+        };
+
+        Some(match ord {
+            Ordering::Less => (self.a.next(), None),
+            Ordering::Greater => (None, self.b.next()),
+            Ordering::Equal => (self.a.next(), self.b.next())
+        })
+    }
+
+    fn union<F: Fn(&I::Item, &I::Item) -> Ordering>(&mut self, cmp: &F) -> Option<I::Item> {
+        match self.step(cmp)? {
+            (Some(x), _) => Some(x),
+            (None, y) => y
+        }
+    }
+
+    fn intersection<F: Fn(&I::Item, &I::Item) -> Ordering>(&mut self, cmp: &F) -> Option<I::Item> {
+        loop {
+            // nothing can match once either side runs out
+            self.a.peek()?;
+            self.b.peek()?;
+            if let (Some(x), Some(_)) = self.step(cmp)? {
+                return Some(x);
+            }
+        }
+    }
+
+    fn difference<F: Fn(&I::Item, &I::Item) -> Ordering>(&mut self, cmp: &F) -> Option<I::Item> {
+        loop {
+            self.a.peek()?;
+            if let (Some(x), None) = self.step(cmp)? {
+                return Some(x);
+            }
+        }
+    }
+
+    fn symmetric_difference<F: Fn(&I::Item, &I::Item) -> Ordering>(&mut self, cmp: &F) -> Option<I::Item> {
+        loop {
+            match self.step(cmp)? {
+                (Some(x), None) => return Some(x),
+                (None, Some(y)) => return Some(y),
+                _ => ()
+            }
+        }
+    }
+}
+
+// The lazy versions walk both trees in the order of the left one.
+macro_rules! set_op {
+    ($name:ident, $op:ident) => {
+        pub struct $name<'a, T, C = Natural> {
+            merge: Merge<Iter<'a, T>>,
+            cmp: &'a C
+        }
+
+        impl<'a, T, C: Compare<T>> Iterator for $name<'a, T, C> {
+            type Item = &'a T;
+
+            fn next(&mut self) -> Option<&'a T> {
+                let cmp = self.cmp;
+                self.merge.$op(&|x: &&T, y: &&T| cmp.compare(x, y))
+            }
+        }
+
+        impl<'a, T, C: Compare<T>> FusedIterator for $name<'a, T, C> {}
+    };
+}
+
+set_op!(Union, union);
+set_op!(Intersection, intersection);
+set_op!(Difference, difference);
+set_op!(SymmetricDifference, symmetric_difference);
+
+impl<T, C: Compare<T>> BTree<T, C> {
+
+    fn merge<'a>(&'a self, other: &'a BTree<T, C>) -> Merge<Iter<'a, T>> {
+        Merge::new(self.iter(), other.iter())
+    }
+
+    pub fn union<'a>(&'a self, other: &'a BTree<T, C>) -> Union<'a, T, C> {
+        Union { merge: self.merge(other), cmp: &self.cmp }
+    }
+
+    pub fn intersection<'a>(&'a self, other: &'a BTree<T, C>) -> Intersection<'a, T, C> {
+        Intersection { merge: self.merge(other), cmp: &self.cmp }
+    }
+
+    pub fn difference<'a>(&'a self, other: &'a BTree<T, C>) -> Difference<'a, T, C> {
+        Difference { merge: self.merge(other), cmp: &self.cmp }
+    }
+
+    pub fn symmetric_difference<'a>(&'a self, other: &'a BTree<T, C>)
+            -> SymmetricDifference<'a, T, C> {
+        SymmetricDifference { merge: self.merge(other), cmp: &self.cmp }
+    }
+
+    pub fn is_subset(&self, other: &BTree<T, C>) -> bool {
+        self.size() <= other.size() && self.difference(other).next().is_none()
+    }
+
+    pub fn is_superset(&self, other: &BTree<T, C>) -> bool {
+        other.is_subset(self)
+    }
+
+    pub fn is_disjoint(&self, other: &BTree<T, C>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+
+    // The into_ versions take both trees apart and build the result straight
+    // from the merged values, so nothing is cloned or inserted one at a time.
+    // The result keeps the left tree's comparator.
+
+    fn into_merge(self, other: BTree<T, C>) -> (Merge<IntoIter<T>>, C) {
+        let a = IntoIter::new(self.nodes, self.root_idx);
+        (Merge::new(a, other.into_iter()), self.cmp)
+    }
+
+    pub fn into_union(self, other: BTree<T, C>) -> BTree<T, C> {
+        let (mut merge, cmp) = self.into_merge(other);
+        let vals: Vec<T> = std::iter::from_fn(|| merge.union(&|x, y| cmp.compare(x, y))).collect();
+        BTree::build_sorted(vals, cmp)
+    }
+
+    pub fn into_intersection(self, other: BTree<T, C>) -> BTree<T, C> {
+        let (mut merge, cmp) = self.into_merge(other);
+        let vals: Vec<T> = std::iter::from_fn(|| merge.intersection(&|x, y| cmp.compare(x, y)))
+            .collect();
+        BTree::build_sorted(vals, cmp)
+    }
+
+    pub fn into_difference(self, other: BTree<T, C>) -> BTree<T, C> {
+        let (mut merge, cmp) = self.into_merge(other);
+        let vals: Vec<T> = std::iter::from_fn(|| merge.difference(&|x, y| cmp.compare(x, y)))
+            .collect();
+        BTree::build_sorted(vals, cmp)
+    }
+
+    pub fn into_symmetric_difference(self, other: BTree<T, C>) -> BTree<T, C> {
+        let (mut merge, cmp) = self.into_merge(other);
+        let vals: Vec<T> = std::iter::from_fn(|| merge.symmetric_difference(&|x, y| cmp.compare(x, y)))
+            .collect();
+        BTree::build_sorted(vals, cmp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn tree_of(vals: &[i32]) -> BTree<i32> {
+        let mut b = BTree::new();
+        for v in vals.iter() {
+            b.insert(*v);
+        }
+        b
+    }
+
+    fn collect<'a, I: Iterator<Item = &'a i32>>(it: I) -> Vec<i32> {
+        it.cloned().collect()
+    }
+
+    #[test]
+    fn test_lazy_ops() {
+        let a = tree_of(&[1, 3, 5, 7, 9]);
+        let b = tree_of(&[3, 4, 5, 6]);
+
+        assert!(collect(a.union(&b)) == vec![1, 3, 4, 5, 6, 7, 9]);
+        assert!(collect(a.intersection(&b)) == vec![3, 5]);
+        assert!(collect(a.difference(&b)) == vec![1, 7, 9]);
+        assert!(collect(b.difference(&a)) == vec![4, 6]);
+        assert!(collect(a.symmetric_difference(&b)) == vec![1, 4, 6, 7, 9]);
+    }
+
+    #[test]
+    fn test_lazy_ops_dups() {
+        let a = tree_of(&[1, 1, 1, 2, 3, 3]);
+        let b = tree_of(&[1, 3, 3, 3, 4]);
+
+        assert!(collect(a.union(&b)) == vec![1, 1, 1, 2, 3, 3, 3, 4]);
+        assert!(collect(a.intersection(&b)) == vec![1, 3, 3]);
+        assert!(collect(a.difference(&b)) == vec![1, 1, 2]);
+        assert!(collect(a.symmetric_difference(&b)) == vec![1, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_lazy_ops_empty() {
+        let a = tree_of(&[1, 2]);
+        let e = BTree::<i32>::new();
+
+        assert!(collect(a.union(&e)) == vec![1, 2]);
+        assert!(collect(e.union(&a)) == vec![1, 2]);
+        assert!(a.intersection(&e).next().is_none());
+        assert!(collect(a.difference(&e)) == vec![1, 2]);
+        assert!(e.difference(&a).next().is_none());
+        assert!(collect(e.symmetric_difference(&a)) == vec![1, 2]);
+    }
+
+    #[test]
+    fn test_predicates() {
+        let a = tree_of(&[2, 4]);
+        let b = tree_of(&[1, 2, 3, 4]);
+        let c = tree_of(&[5, 6]);
+        let d = tree_of(&[2, 2, 4]);
+
+        assert!(a.is_subset(&b));
+        assert!(!b.is_subset(&a));
+        assert!(b.is_superset(&a));
+        assert!(!d.is_subset(&b));
+        assert!(a.is_subset(&d));
+        assert!(a.is_disjoint(&c));
+        assert!(!a.is_disjoint(&b));
+        assert!(BTree::<i32>::new().is_subset(&a));
+        assert!(BTree::<i32>::new().is_disjoint(&a));
+    }
+
+    #[test]
+    fn test_owned_ops() {
+        let u = tree_of(&[1, 3, 5, 7, 9]).into_union(tree_of(&[3, 4, 5, 6]));
+        assert!(collect(u.iter()) == vec![1, 3, 4, 5, 6, 7, 9]);
+        assert_all(&u);
+
+        let i = tree_of(&[1, 3, 5, 7, 9]).into_intersection(tree_of(&[3, 4, 5, 6]));
+        assert!(collect(i.iter()) == vec![3, 5]);
+        assert_all(&i);
+
+        let d = tree_of(&[1, 3, 5, 7, 9]).into_difference(tree_of(&[3, 4, 5, 6]));
+        assert!(collect(d.iter()) == vec![1, 7, 9]);
+        assert_all(&d);
+
+        let s = tree_of(&[1, 3, 5, 7, 9]).into_symmetric_difference(tree_of(&[3, 4, 5, 6]));
+        assert!(collect(s.iter()) == vec![1, 4, 6, 7, 9]);
+        assert_all(&s);
+
+        let e = BTree::<i32>::new().into_union(BTree::new());
+        assert!(e.is_empty());
+        assert_all(&e);
+    }
+
+    #[test]
+    fn test_built_tree_is_usable() {
+        let mut evens = BTree::new();
+        let mut odds = BTree::new();
+        let mut i = 0;
+        while i < 50 {
+            evens.insert(i * 2);
+            odds.insert(i * 2 + 1);
+            i += 1;
+        }
+
+        let mut all = evens.into_union(odds);
+        assert!(all.size() == 100);
+        assert_all(&all);
+        assert!(all.select(37) == Some(&37));
+        assert!(all.rank(&50) == 50);
+
+        all.insert(1000);
+        all.remove(&0);
+        assert!(all.first() == Some(&1));
+        assert!(all.last() == Some(&1000));
+    }
+}
@@ -0,0 +1,103 @@
+use std::error::Error;
+
+use crate::*;
+
+// Input to from_sorted_iter_checked that was out of order: the value at
+// position index of the input comes before the one ahead of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotSorted {
+    pub index: usize
+}
+
+impl fmt::Display for NotSorted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "value {} of the input is out of order with the one before it", self.index)
+    }
+}
+
+impl Error for NotSorted {}
+
+impl<T: Ord> BTree<T> {
+
+    // Builds a perfectly balanced tree from values that are already in
+    // ascending order, without comparing or rotating anything. The order is
+    // not checked, except in debug builds, and if it is wrong the tree is
+    // too. Takes O(n).
+    pub fn from_sorted_iter<I: IntoIterator<Item = T>>(vals: I) -> Self {
+        BTree::from_sorted_iter_with_comparator(vals, Natural)
+    }
+
+    // Like from_sorted_iter, but checks the order as it goes and stops at the
+    // first value that is out of place. Takes O(n).
+    pub fn from_sorted_iter_checked<I: IntoIterator<Item = T>>(vals: I)
+            -> Result<Self, NotSorted> {
+        BTree::from_sorted_iter_checked_with_comparator(vals, Natural)
+    }
+}
+
+impl<T, C: Compare<T>> BTree<T, C> {
+
+    // from_sorted_iter for values in the order cmp gives
+    pub fn from_sorted_iter_with_comparator<I: IntoIterator<Item = T>>(vals: I, cmp: C) -> Self {
+        let b = BTree::build_sorted(vals, cmp);
+        debug_assert!(assert_all(&b));
+        b
+    }
+
+    pub fn from_sorted_iter_checked_with_comparator<I: IntoIterator<Item = T>>(vals: I, cmp: C)
+            -> Result<Self, NotSorted> {
+        let vals = vals.into_iter();
+        let mut nodes = Arena::with_capacity(vals.size_hint().0);
+        // a fresh arena fills its slots in order, so the last value is in
+        // slot index - 1
+        for (index, val) in vals.enumerate() {
+            if index > 0 && cmp.compare(&nodes[index - 1].val, &val) == Ordering::Greater {
+                return Err(NotSorted { index });
+            }
+            nodes.insert(Self::unlinked(val));
+        }
+        Ok(Self::link_arena(nodes, cmp))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_from_sorted_iter() {
+        // filter gives no size hint, so the arena has to grow as it goes
+        let b = BTree::from_sorted_iter((0..1000).filter(|i| i % 3 != 0));
+        assert!(b.size() == 666 && b.validate() == Ok(()));
+        assert!(b.iter().cloned().eq((0..1000).filter(|i| i % 3 != 0)));
+        let s = b.stats();
+        assert!(s.height == 10 && s.max_leaf_depth - s.min_leaf_depth <= 1);
+
+        let desc = |a: &i32, b: &i32| b.cmp(a);
+        let b = BTree::from_sorted_iter_with_comparator(vec![5, 3, 3, 1], desc);
+        assert!(b.validate() == Ok(()));
+        assert!(b.first() == Some(&5));
+    }
+
+    #[test]
+    fn test_from_sorted_iter_checked() {
+        let b = BTree::from_sorted_iter_checked(vec!["a", "b", "b", "c"]).unwrap();
+        assert!(b.size() == 4 && b.validate() == Ok(()));
+        assert!(BTree::<i32>::from_sorted_iter_checked(vec![]).unwrap().is_empty());
+
+        let err = BTree::from_sorted_iter_checked(vec![1, 2, 5, 4, 6]).unwrap_err();
+        assert!(err == NotSorted { index: 3 });
+        assert!(err.to_string() == "value 3 of the input is out of order with the one before it");
+
+        // nothing after the first bad value is read
+        let mut read = 0;
+        let vals = [1, 0, 2, 3].iter().map(|&v| { read += 1; v });
+        assert!(BTree::from_sorted_iter_checked(vals).unwrap_err() == NotSorted { index: 1 });
+        assert!(read == 2);
+
+        let desc = |a: &i32, b: &i32| b.cmp(a);
+        assert!(BTree::from_sorted_iter_checked_with_comparator(vec![3, 2, 1], desc).is_ok());
+        let err = BTree::from_sorted_iter_checked_with_comparator(vec![3, 1, 2], desc).unwrap_err();
+        assert!(err.index == 2);
+    }
+}
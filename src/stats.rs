@@ -0,0 +1,140 @@
+use crate::*;
+
+// A summary of the shape of a tree. Depths count edges, so the root is at
+// depth 0, and a leaf is a node with no children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    pub len: usize,
+    // nodes on the longest path down from the root, 0 if the tree is empty
+    pub height: usize,
+    // black nodes on every path from the root down to an empty child, root
+    // included
+    pub black_height: usize,
+    pub red: usize,
+    pub black: usize,
+    pub leaves: usize,
+    pub min_leaf_depth: usize,
+    pub max_leaf_depth: usize,
+    // 0 if the tree is empty
+    pub avg_leaf_depth: f64,
+    // how many nodes the arena can hold, and how many of those are unused
+    pub capacity: usize,
+    pub slack: usize
+}
+
+impl TreeStats {
+
+    // The tallest a red-black tree with this many nodes can be,
+    // floor(2 * log2(n + 1)). height never goes over it.
+    pub fn height_bound(&self) -> usize {
+        (2.0 * ((self.len + 1) as f64).log2()).floor() as usize
+    }
+}
+
+impl<T, C> BTree<T, C> {
+
+    // Takes O(n).
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            len: self.size(),
+            height: 0,
+            black_height: 0,
+            red: 0,
+            black: 0,
+            leaves: 0,
+            min_leaf_depth: 0,
+            max_leaf_depth: 0,
+            avg_leaf_depth: 0.0,
+            capacity: self.capacity(),
+            slack: self.capacity() - self.size()
+        };
+        if self.root_idx == EMPTY {
+            return stats;
+        }
+
+        stats.min_leaf_depth = usize::MAX;
+        let mut depth_total = 0;
+        // (node, its depth, black nodes from the root down to it)
+        let mut stack = vec![(self.root_idx, 0, 0)];
+        while let Some((idx, depth, mut blacks)) = stack.pop() {
+            let n = &self.nodes[idx];
+            if n.color == RED {
+                stats.red += 1;
+            } else {
+                stats.black += 1;
+                blacks += 1;
+            }
+
+            if n.left == EMPTY || n.right == EMPTY {
+                // every path to an empty child has the same count
+                stats.black_height = blacks;
+            }
+            if n.left == EMPTY && n.right == EMPTY {
+                stats.leaves += 1;
+                stats.min_leaf_depth = stats.min_leaf_depth.min(depth);
+                stats.max_leaf_depth = stats.max_leaf_depth.max(depth);
+                depth_total += depth;
+            }
+
+            for &child in [n.left, n.right].iter() {
+                if child != EMPTY {
+                    stack.push((child, depth + 1, blacks));
+                }
+            }
+        }
+
+        stats.height = stats.max_leaf_depth + 1;
+        stats.avg_leaf_depth = depth_total as f64 / stats.leaves as f64;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_stats_empty() {
+        let s = BTree::<i32>::with_capacity(4).stats();
+        assert!(s.len == 0 && s.height == 0 && s.black_height == 0 && s.leaves == 0);
+        assert!(s.avg_leaf_depth == 0.0);
+        assert!(s.slack == s.capacity && s.capacity >= 4);
+        assert!(s.height_bound() == 0);
+    }
+
+    #[test]
+    fn test_stats_shape() {
+        // 2..9 is perfect and all black, and 1 hangs red off of 2
+        let mut b = BTree::build_sorted(1..9, Natural);
+        let s = b.stats();
+        assert!(s.len == 8 && s.height == 4);
+        assert!(s.black_height == 3);
+        assert!(s.red == 1 && s.black == 7);
+        assert!(s.leaves == 4);
+        assert!(s.min_leaf_depth == 2 && s.max_leaf_depth == 3);
+        assert!(s.avg_leaf_depth == 2.25);
+        assert!(s.slack == s.capacity - 8);
+
+        b.shrink_to_fit();
+        assert!(b.stats().slack == 0);
+    }
+
+    #[test]
+    fn test_height_within_bound() {
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < 1000 {
+            b.insert(i);
+            let s = b.stats();
+            assert!(s.height <= s.height_bound());
+            assert!(s.red + s.black == s.len);
+            i += 1;
+        }
+        while i > 0 {
+            i -= 2;
+            b.remove(&i);
+            let s = b.stats();
+            assert!(s.height <= s.height_bound());
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::error::Error;
+
+use crate::*;
+
+// The first broken rule validate comes across, along with the arena indices
+// of the nodes involved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    // the root is red
+    RootNotBlack { root: usize },
+    // a red node has a red child
+    RedRed { parent: usize, child: usize },
+    // the paths down the left and right of node pass through different numbers
+    // of black nodes
+    BlackHeight { node: usize, left: usize, right: usize },
+    // before comes ahead of after in the tree, but the comparator puts it after
+    Order { before: usize, after: usize },
+    // node is linked as a child of parent, but its own parent link says found
+    // (EMPTY for the root). Also reported if node is reached twice, or a link
+    // points outside the arena.
+    ParentLink { node: usize, parent: usize, found: usize },
+    // the size stored in node does not match the number of nodes below it
+    SizeMismatch { node: usize, stored: usize, actual: usize },
+    // slot holds a node that can not be reached from the root
+    Unreachable { slot: usize }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            InvariantViolation::RootNotBlack { root } =>
+                write!(f, "root {} is not black", root),
+            InvariantViolation::RedRed { parent, child } =>
+                write!(f, "{} is a red child of red node {}", child, parent),
+            InvariantViolation::BlackHeight { node, left, right } =>
+                write!(f, "{} has black height {} on the left but {} on the right",
+                        node, left, right),
+            InvariantViolation::Order { before, after } =>
+                write!(f, "{} comes before {} but is greater", before, after),
+            InvariantViolation::ParentLink { node, parent, found } =>
+                write!(f, "{} is a child of {} but links to {} as its parent",
+                        node, parent as isize, found as isize),
+            InvariantViolation::SizeMismatch { node, stored, actual } =>
+                write!(f, "{} has size {}, but its subtree has {} nodes", node, stored, actual),
+            InvariantViolation::Unreachable { slot } =>
+                write!(f, "{} can not be reached from the root", slot)
+        }
+    }
+}
+
+impl Error for InvariantViolation {}
+
+impl<T, C: Compare<T>> BTree<T, C> {
+
+    // Checks every invariant the tree relies on without panicking, so it is
+    // safe to run on a tree that may be corrupt. Takes O(n).
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let n = self.nodes.slots_len();
+        let root = self.root_idx;
+        if root == EMPTY {
+            return match self.nodes.iter().next() {
+                None => Ok(()),
+                Some((slot, _)) => Err(InvariantViolation::Unreachable { slot })
+            };
+        }
+        // links into vacant slots count as broken too
+        let parent_of = |idx| self.nodes.get(idx).map_or(EMPTY, |node| node.parent);
+        if self.nodes.get(root).is_none() || parent_of(root) != EMPTY {
+            let found = parent_of(root);
+            return Err(InvariantViolation::ParentLink { node: root, parent: EMPTY, found });
+        }
+        if self.nodes[root].color == RED {
+            return Err(InvariantViolation::RootNotBlack { root });
+        }
+
+        // walk down from the root with a stack, so a badly broken tree can not
+        // overflow the call stack. Parents always come before their children.
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut seen = vec![false; n];
+        let mut stack = vec![root];
+        seen[root] = true;
+        while let Some(idx) = stack.pop() {
+            order.push(idx);
+            for &child in [self.nodes[idx].left, self.nodes[idx].right].iter() {
+                if child == EMPTY {
+                    continue;
+                }
+                if self.nodes.get(child).is_none() || seen[child] || parent_of(child) != idx {
+                    let found = parent_of(child);
+                    return Err(InvariantViolation::ParentLink { node: child, parent: idx, found });
+                }
+                if self.nodes[idx].color == RED && self.nodes[child].color == RED {
+                    return Err(InvariantViolation::RedRed { parent: idx, child });
+                }
+                seen[child] = true;
+                stack.push(child);
+            }
+        }
+
+        if let Some((slot, _)) = self.nodes.iter().find(|&(idx, _)| !seen[idx]) {
+            return Err(InvariantViolation::Unreachable { slot });
+        }
+
+        // children before parents, so both sides are done by the time we
+        // get to a node
+        let mut black = vec![0; n];
+        for &idx in order.iter().rev() {
+            let (left, right) = (self.nodes[idx].left, self.nodes[idx].right);
+            let actual = 1 + subtree_size(&self.nodes, left) + subtree_size(&self.nodes, right);
+            if self.nodes[idx].size != actual {
+                return Err(InvariantViolation::SizeMismatch {
+                    node: idx,
+                    stored: self.nodes[idx].size,
+                    actual
+                });
+            }
+
+            let left_black = if left == EMPTY { 0 } else { black[left] };
+            let right_black = if right == EMPTY { 0 } else { black[right] };
+            if left_black != right_black {
+                return Err(InvariantViolation::BlackHeight {
+                    node: idx,
+                    left: left_black,
+                    right: right_black
+                });
+            }
+            black[idx] = left_black + if self.nodes[idx].color == BLACK { 1 } else { 0 };
+        }
+
+        // the links are sound now, so an in-order walk is safe
+        let mut idx = min_in_subtree(&self.nodes, root);
+        let mut next = next_idx(&self.nodes, idx);
+        while next != EMPTY {
+            if self.cmp.compare(&self.nodes[idx].val, &self.nodes[next].val) == Ordering::Greater {
+                return Err(InvariantViolation::Order { before: idx, after: next });
+            }
+            idx = next;
+            next = next_idx(&self.nodes, idx);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn make_tree(n: i32) -> BTree<i32> {
+        let mut b = BTree::new();
+        let mut i = 0;
+        while i < n {
+            b.insert((i * 11) % n);
+            i += 1;
+        }
+        b
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(BTree::<i32>::new().validate() == Ok(()));
+        let mut b = make_tree(50);
+        assert!(b.validate() == Ok(()));
+        let mut i = 0;
+        while i < 50 {
+            b.remove(&((i * 3) % 50));
+            assert!(b.validate() == Ok(()));
+            i += 1;
+        }
+    }
+
+    #[test]
+    fn test_root_and_colors() {
+        let mut b = BTree::build_sorted(0..3, Natural);
+        b.nodes[1].color = RED;
+        assert!(b.validate() == Err(InvariantViolation::RootNotBlack { root: 1 }));
+
+        let mut b = BTree::build_sorted(0..7, Natural);
+        b.nodes[1].color = RED;
+        b.nodes[0].color = RED;
+        assert!(b.validate() == Err(InvariantViolation::RedRed { parent: 1, child: 0 }));
+
+        let mut b = BTree::build_sorted(0..3, Natural);
+        b.nodes[0].color = RED;
+        assert!(b.validate() == Err(InvariantViolation::BlackHeight { node: 1, left: 0, right: 1 }));
+    }
+
+    #[test]
+    fn test_links_and_sizes() {
+        let mut b = BTree::build_sorted(0..3, Natural);
+        b.nodes[2].parent = 0;
+        assert!(b.validate() == Err(InvariantViolation::ParentLink { node: 2, parent: 1, found: 0 }));
+
+        let mut b = BTree::build_sorted(0..3, Natural);
+        b.nodes[1].right = 9;
+        let err = b.validate().unwrap_err();
+        assert!(err == InvariantViolation::ParentLink { node: 9, parent: 1, found: EMPTY });
+
+        let mut b = BTree::build_sorted(0..3, Natural);
+        b.nodes[1].size = 4;
+        assert!(b.validate()
+                == Err(InvariantViolation::SizeMismatch { node: 1, stored: 4, actual: 3 }));
+
+        let mut b = BTree::build_sorted(0..3, Natural);
+        b.nodes[1].right = EMPTY;
+        b.nodes[1].size = 2;
+        assert!(b.validate() == Err(InvariantViolation::Unreachable { slot: 2 }));
+    }
+
+    #[test]
+    fn test_order() {
+        let mut b = BTree::build_sorted(0..7, Natural);
+        b.nodes[2].val = 10;
+        assert!(b.validate() == Err(InvariantViolation::Order { before: 2, after: 3 }));
+        assert!(b.validate().unwrap_err().to_string() == "2 comes before 3 but is greater");
+    }
+}